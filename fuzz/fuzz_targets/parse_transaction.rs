@@ -0,0 +1,28 @@
+#![no_main]
+#![allow(dead_code)]
+#[macro_use]
+extern crate libfuzzer_sys;
+extern crate chrono;
+extern crate sha2;
+
+// the crate is a binary, so the modules are compiled into the target directly
+#[path = "../../src/clock.rs"]
+mod clock;
+#[path = "../../src/hex.rs"]
+mod hex;
+#[path = "../../src/transaction.rs"]
+mod transaction;
+
+use std::str;
+
+use transaction::Transaction;
+
+// run with: $ cargo fuzz run parse_transaction
+fuzz_target!(|data: &[u8]| {
+    if let Ok(s) = str::from_utf8(data) {
+        if let Ok(tx) = Transaction::try_parse(s) {
+            let _ = tx.hash().short(8);
+            let _ = tx.to_string();
+        }
+    }
+});
@@ -0,0 +1,210 @@
+use std::io::Cursor;
+
+use rocket::http::{ContentType, Status};
+use rocket::request::Request;
+use rocket::response::{self, Responder, Response};
+
+use crate::transaction::{Error, VerifyError};
+use crate::transaction_log::{FileError, DANGLING_CHAIN, LOG_FULL};
+
+/// Error returned by all routes, serialized as
+/// `{"status": 400, "code": "bad_timestamp", "message": "..."}`.
+/// The `code` strings are stable and meant to be matched by clients.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ApiError {
+    pub status: Status,
+    pub code: &'static str,
+    pub message: String,
+}
+
+#[derive(Debug, Serialize)]
+struct ApiErrorBody<'a> {
+    status: u16,
+    code: &'a str,
+    message: &'a str,
+}
+
+impl ApiError {
+    pub fn new<S: Into<String>>(
+        status: Status,
+        code: &'static str,
+        message: S,
+    ) -> Self {
+        ApiError {
+            status,
+            code,
+            message: message.into(),
+        }
+    }
+
+    pub fn bad_request<S: Into<String>>(
+        code: &'static str,
+        message: S,
+    ) -> Self {
+        Self::new(Status::BadRequest, code, message)
+    }
+
+    pub fn to_json(&self) -> String {
+        serde_json::to_string(&ApiErrorBody {
+            status: self.status.code,
+            code: self.code,
+            message: &self.message,
+        }).expect("error body is always serializable")
+    }
+}
+
+/// Error of a route, keeping the underlying cause. It is logged on the
+/// server and turned into a clean `ApiError` for the client.
+#[derive(Debug)]
+pub enum RouteError {
+    /// Reading or writing the transaction log failed.
+    Log(FileError),
+    /// The request could not be parsed or validated; carries the `code`.
+    Invalid(&'static str, Error),
+    /// A ready made client error.
+    Api(ApiError),
+}
+
+impl From<FileError> for RouteError {
+    fn from(err: FileError) -> Self {
+        RouteError::Log(err)
+    }
+}
+
+impl From<()> for RouteError {
+    fn from(err: ()) -> Self {
+        RouteError::Log(FileError::No(err))
+    }
+}
+
+impl From<ApiError> for RouteError {
+    fn from(err: ApiError) -> Self {
+        RouteError::Api(err)
+    }
+}
+
+fn error_message(err: Error) -> String {
+    match err {
+        Error::IllegalArgument(msg) | Error::ParseError(msg) => msg,
+    }
+}
+
+impl From<RouteError> for ApiError {
+    fn from(err: RouteError) -> Self {
+        match err {
+            RouteError::Log(FileError::Transaction(e)) => {
+                ApiError::bad_request("illegal_argument", error_message(e))
+            }
+            RouteError::Log(FileError::Verify(e)) => {
+                let message = match e {
+                    VerifyError::NonConsecutiveID(prev, id) => format!(
+                        "Transaction {} does not follow {}",
+                        id,
+                        prev
+                    ),
+                    VerifyError::MissmatchingHash {
+                        id,
+                        expected,
+                        actual,
+                    } => format!(
+                        "Hash of transaction {} is {}, but should be {}",
+                        id,
+                        actual,
+                        expected
+                    ),
+                };
+                ApiError::new(Status::Conflict, "verify_failed", message)
+            }
+            RouteError::Log(FileError::Other(ref msg)) if msg == LOG_FULL => {
+                ApiError::new(
+                    Status::InsufficientStorage,
+                    "log_full",
+                    "The transaction log is full",
+                )
+            }
+            RouteError::Log(FileError::Other(ref msg))
+                if msg == DANGLING_CHAIN =>
+            {
+                ApiError::new(
+                    Status::Conflict,
+                    "dangling_chain",
+                    "Only the oldest transactions can be pruned, and not all",
+                )
+            }
+            RouteError::Log(_) => ApiError::new(
+                Status::InternalServerError,
+                "log_error",
+                "Transaction log error",
+            ),
+            RouteError::Invalid(code, e) => {
+                ApiError::bad_request(code, error_message(e))
+            }
+            RouteError::Api(e) => e,
+        }
+    }
+}
+
+impl<'r> Responder<'r, 'static> for RouteError {
+    fn respond_to(self, req: &'r Request<'_>) -> response::Result<'static> {
+        println!(
+            "Error handling {} {}: {:?}",
+            req.method(),
+            req.uri(),
+            &self
+        );
+        ApiError::from(self).respond_to(req)
+    }
+}
+
+impl<'r> Responder<'r, 'static> for ApiError {
+    fn respond_to(self, _: &'r Request<'_>) -> response::Result<'static> {
+        let body = self.to_json();
+        Response::build()
+            .status(self.status)
+            .header(ContentType::JSON)
+            .sized_body(body.len(), Cursor::new(body))
+            .ok()
+    }
+}
+
+
+#[cfg(test)]
+mod test {
+
+    use super::*;
+    use std::io;
+
+    #[test]
+    fn route_error_statuses() {
+        let io = RouteError::from(FileError::Io(
+            io::Error::new(io::ErrorKind::Other, "disk on fire"),
+        ));
+        let io = ApiError::from(io);
+        assert_eq!(io.status, Status::InternalServerError);
+        assert_eq!(io.code, "log_error");
+        assert!(!io.message.contains("disk on fire"));
+
+        let verify = RouteError::from(FileError::Verify(
+            VerifyError::MissmatchingHash {
+                id: 2,
+                expected: "AB".to_owned(),
+                actual: "CD".to_owned(),
+            },
+        ));
+        let verify = ApiError::from(verify);
+        assert_eq!(verify.status, Status::Conflict);
+        assert_eq!(verify.code, "verify_failed");
+        assert!(verify.message.contains("is CD, but should be AB"));
+        assert_ne!(io.status, verify.status);
+
+        let full = RouteError::from(FileError::Other(LOG_FULL.to_owned()));
+        let full = ApiError::from(full);
+        assert_eq!(full.status, Status::InsufficientStorage);
+        assert_eq!(full.code, "log_full");
+
+        let dangling = FileError::Other(DANGLING_CHAIN.to_owned());
+        let dangling = ApiError::from(RouteError::from(dangling));
+        assert_eq!(dangling.status, Status::Conflict);
+        assert_eq!(dangling.code, "dangling_chain");
+    }
+}
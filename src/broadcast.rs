@@ -0,0 +1,199 @@
+use std::collections::VecDeque;
+use std::sync::{Condvar, Mutex, MutexGuard};
+use std::time::{Duration, Instant};
+
+use rocket::tokio::sync::mpsc::{unbounded_channel, UnboundedReceiver,
+                                UnboundedSender};
+
+use crate::transaction::Transaction;
+
+/// Hands every published transaction to all current subscribers, and
+/// wakes everyone waiting for a new one.
+#[derive(Debug, Default)]
+pub struct Broadcast {
+    subscribers: Mutex<Vec<UnboundedSender<Transaction>>>,
+    /// Id of the last published transaction.
+    last_id: Mutex<Option<u32>>,
+    published: Condvar,
+}
+
+impl Broadcast {
+    pub fn new() -> Self {
+        Broadcast::default()
+    }
+
+    /// Subscribes to all transactions published from now on.
+    pub fn subscribe(&self) -> UnboundedReceiver<Transaction> {
+        let (tx, rx) = unbounded_channel();
+        self.lock().push(tx);
+        rx
+    }
+
+    /// Sends `tx` to all subscribers and forgets the ones that hung up.
+    pub fn publish(&self, tx: &Transaction) {
+        self.lock().retain(|s| s.send(tx.clone()).is_ok());
+        *self.lock_last_id() = Some(tx.id().inner());
+        self.published.notify_all();
+    }
+
+    /// Blocks for up to `timeout` until a transaction other than `after` is
+    /// the last one published. Returns whether one is, right away if it
+    /// was published before the call.
+    pub fn wait_after(&self, after: u32, timeout: Duration) -> bool {
+        let deadline = Instant::now() + timeout;
+        let mut last_id = self.lock_last_id();
+        loop {
+            if last_id.map_or(false, |id| id != after) {
+                return true;
+            }
+            let now = Instant::now();
+            if now >= deadline {
+                return false;
+            }
+            let (guard, _) = self.published
+                .wait_timeout(last_id, deadline - now)
+                .unwrap_or_else(|poisoned| poisoned.into_inner());
+            last_id = guard;
+        }
+    }
+
+    /// Number of subscribers, without the ones that hung up.
+    pub fn subscriber_count(&self) -> usize {
+        let mut subscribers = self.lock();
+        subscribers.retain(|s| !s.is_closed());
+        subscribers.len()
+    }
+
+    fn lock(&self) -> MutexGuard<'_, Vec<UnboundedSender<Transaction>>> {
+        self.subscribers
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner())
+    }
+
+    fn lock_last_id(&self) -> MutexGuard<Option<u32>> {
+        self.last_id
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner())
+    }
+}
+
+/// Server-sent events stream emitting one event per transaction, first the
+/// `backlog` and then everything received on the subscription. Dropping the
+/// stream ends the subscription, so a client going away releases it right
+/// away instead of at the next publish.
+#[derive(Debug)]
+pub struct EventStream {
+    backlog: VecDeque<Transaction>,
+    rx: UnboundedReceiver<Transaction>,
+}
+
+impl EventStream {
+    pub fn new(
+        backlog: Vec<Transaction>,
+        rx: UnboundedReceiver<Transaction>,
+    ) -> Self {
+        EventStream {
+            backlog: backlog.into_iter().collect(),
+            rx,
+        }
+    }
+
+    /// The next event, waiting for a transaction to be published. `None`
+    /// once the broadcast is gone.
+    pub async fn next(&mut self) -> Option<Vec<u8>> {
+        let tx = match self.backlog.pop_front() {
+            Some(tx) => tx,
+            None => self.rx.recv().await?,
+        };
+        Some(Self::event(&tx))
+    }
+
+    fn event(tx: &Transaction) -> Vec<u8> {
+        format!("id: {}\ndata: {}\n\n", tx.id(), tx).into_bytes()
+    }
+}
+
+
+#[cfg(test)]
+mod test {
+
+    use super::*;
+    use std::env;
+    use std::fs::{self, File};
+    use std::process;
+    use std::sync::Arc;
+    use std::thread;
+    use crate::transaction::*;
+    use crate::transaction_log::{DualLog, TransactionLog};
+
+    fn tx(id: u32) -> Transaction {
+        Transaction::new(
+            TransactionId::new(id).unwrap(),
+            "041017-10:00:00".parse().unwrap(),
+            TransactionData::new(1, 2, "streamed").unwrap(),
+            None,
+        )
+    }
+
+    async fn read_event(stream: &mut EventStream) -> String {
+        String::from_utf8(stream.next().await.unwrap()).unwrap()
+    }
+
+    #[rocket::async_test]
+    async fn stream_receives_published() {
+        let broadcast = Broadcast::new();
+        let mut stream = EventStream::new(vec![tx(1)], broadcast.subscribe());
+        broadcast.publish(&tx(2));
+
+        assert_eq!(
+            read_event(&mut stream).await,
+            format!("id: 00000001\ndata: {}\n\n", tx(1))
+        );
+        assert_eq!(
+            read_event(&mut stream).await,
+            format!("id: 00000002\ndata: {}\n\n", tx(2))
+        );
+
+        drop(broadcast);
+        assert!(stream.next().await.is_none());
+    }
+
+    #[test]
+    fn disconnected_subscribers_are_dropped() {
+        let broadcast = Broadcast::new();
+        let stream = EventStream::new(Vec::new(), broadcast.subscribe());
+        let _other = broadcast.subscribe();
+        assert_eq!(broadcast.subscriber_count(), 2);
+        // hung up subscribers are gone without waiting for a publish
+        drop(stream);
+        assert_eq!(broadcast.subscriber_count(), 1);
+        broadcast.publish(&tx(1));
+        assert_eq!(broadcast.subscriber_count(), 1);
+    }
+
+    #[test]
+    fn create_wakes_waiting() {
+        let path = env::temp_dir()
+            .join(format!("tx_broadcast_wait_{}.txt", process::id()));
+        File::create(&path).unwrap();
+        let broadcast = Arc::new(Broadcast::new());
+        let mut log = DualLog::load(path.clone())
+            .unwrap()
+            .with_broadcast(broadcast.clone());
+        log.create(TransactionData::new(1, 2, "first").unwrap(), None)
+            .unwrap();
+        assert!(broadcast.wait_after(0, Duration::from_millis(0)));
+        assert!(!broadcast.wait_after(1, Duration::from_millis(50)));
+
+        let creator = thread::spawn(move || {
+            thread::sleep(Duration::from_millis(100));
+            log.create(TransactionData::new(1, 2, "second").unwrap(), None)
+                .unwrap()
+        });
+        let start = Instant::now();
+        assert!(broadcast.wait_after(1, Duration::from_secs(10)));
+        assert!(start.elapsed() < Duration::from_secs(10));
+        assert_eq!(creator.join().unwrap().id().inner(), 2);
+        fs::remove_file(&path).unwrap();
+    }
+}
@@ -0,0 +1,259 @@
+use std::collections::{HashMap, VecDeque};
+use std::ops::{Deref, DerefMut};
+use std::path::Path;
+use std::sync::{Mutex, MutexGuard};
+
+use crate::transaction::{Transaction, TransactionTime};
+use crate::transaction_log::{DualLog, FileError, GetAll, GetById};
+
+/// Read-through cache of the `capacity` most recently read transactions of
+/// a log. Transactions never change once created, so cached entries stay
+/// valid as long as the inner log only grows. Everything else derefs to the
+/// inner log, so anything else changing it has to `clear` the cache.
+#[derive(Debug)]
+pub struct CachingLog<L> {
+    inner: L,
+    capacity: usize,
+    cache: Mutex<Lru>,
+}
+
+#[derive(Debug, Default)]
+struct Lru {
+    entries: HashMap<u32, Transaction>,
+    /// Ids from least to most recently used.
+    order: VecDeque<u32>,
+}
+
+impl Lru {
+    fn get(&mut self, id: u32) -> Option<Transaction> {
+        let tx = self.entries.get(&id).cloned();
+        if tx.is_some() {
+            self.touch(id);
+        }
+        tx
+    }
+
+    fn insert(&mut self, tx: Transaction, capacity: usize) {
+        let id = tx.id().inner();
+        if self.entries.insert(id, tx).is_some() {
+            self.touch(id);
+            return;
+        }
+        self.order.push_back(id);
+        while self.order.len() > capacity {
+            if let Some(oldest) = self.order.pop_front() {
+                self.entries.remove(&oldest);
+            }
+        }
+    }
+
+    fn touch(&mut self, id: u32) {
+        if let Some(pos) = self.order.iter().position(|&i| i == id) {
+            self.order.remove(pos);
+        }
+        self.order.push_back(id);
+    }
+}
+
+impl<L> CachingLog<L> {
+    /// Caches up to `capacity` transactions, none for 0.
+    pub fn new(inner: L, capacity: usize) -> Self {
+        CachingLog {
+            inner,
+            capacity,
+            cache: Mutex::new(Lru::default()),
+        }
+    }
+
+    pub fn inner(&self) -> &L {
+        &self.inner
+    }
+
+    /// Number of cached transactions.
+    pub fn cached(&self) -> usize {
+        self.lock().entries.len()
+    }
+
+    /// Forgets all cached transactions.
+    pub fn clear(&mut self) {
+        *self.lock() = Lru::default();
+    }
+
+    fn lock(&self) -> MutexGuard<'_, Lru> {
+        self.cache.lock().unwrap_or_else(|poisoned| poisoned.into_inner())
+    }
+}
+
+impl<L> Deref for CachingLog<L> {
+    type Target = L;
+    fn deref(&self) -> &L {
+        &self.inner
+    }
+}
+
+impl<L> DerefMut for CachingLog<L> {
+    fn deref_mut(&mut self) -> &mut L {
+        &mut self.inner
+    }
+}
+
+/// The changes of a `DualLog` which rewrite its transactions.
+impl<P: AsRef<Path>> CachingLog<DualLog<P>> {
+    /// See `DualLog::prune_older_than`, the remaining hashes change.
+    pub fn prune_older_than(
+        &mut self,
+        cutoff: &TransactionTime,
+    ) -> Result<usize, FileError> {
+        let pruned = self.inner.prune_older_than(cutoff)?;
+        if pruned > 0 {
+            self.clear();
+        }
+        Ok(pruned)
+    }
+
+    /// See `DualLog::reset_if_missing`.
+    pub fn reset_if_missing(&mut self) -> bool {
+        let reset = self.inner.reset_if_missing();
+        if reset {
+            self.clear();
+        }
+        reset
+    }
+}
+
+impl<L: GetById> GetById for CachingLog<L> {
+    type Error = L::Error;
+    fn get_by_id(&self, id: u32) -> Result<Option<Transaction>, Self::Error> {
+        if let Some(tx) = self.lock().get(id) {
+            return Ok(Some(tx));
+        }
+        let tx = self.inner.get_by_id(id)?;
+        if let Some(ref tx) = tx {
+            if self.capacity > 0 {
+                self.lock().insert(tx.clone(), self.capacity);
+            }
+        }
+        Ok(tx)
+    }
+
+    fn contains(&self, id: u32) -> Result<bool, Self::Error> {
+        if self.lock().entries.contains_key(&id) {
+            return Ok(true);
+        }
+        self.inner.contains(id)
+    }
+}
+
+impl<L: GetAll> GetAll for CachingLog<L> {
+    type Error = L::Error;
+    fn get_all(&self) -> Result<Vec<Transaction>, Self::Error> {
+        self.inner.get_all()
+    }
+}
+
+
+#[cfg(test)]
+mod test {
+
+    use super::*;
+    use std::cell::Cell;
+    use std::env;
+    use std::fs;
+    use std::process;
+    use crate::transaction::TransactionData;
+    use crate::transaction_log::{FullTransactionLog, TransactionLog};
+
+    /// Counts the reads reaching the log.
+    struct CountingLog {
+        log: FullTransactionLog,
+        reads: Cell<usize>,
+    }
+
+    impl GetById for CountingLog {
+        type Error = ();
+        fn get_by_id(&self, id: u32) -> Result<Option<Transaction>, ()> {
+            self.reads.set(self.reads.get() + 1);
+            self.log.get_by_id(id)
+        }
+    }
+
+    fn counting_log(n: u8) -> CountingLog {
+        let mut log = FullTransactionLog::new();
+        for i in 0..n {
+            let data = TransactionData::new(i, 2, "cached").unwrap();
+            log.create(data, None).unwrap();
+        }
+        CountingLog {
+            log,
+            reads: Cell::new(0),
+        }
+    }
+
+    #[test]
+    fn second_read_is_cached() {
+        let log = CachingLog::new(counting_log(5), 2);
+        let first = log.get_by_id(1).unwrap().unwrap();
+        assert_eq!(log.inner().reads.get(), 1);
+        let second = log.get_by_id(1).unwrap().unwrap();
+        assert_eq!(log.inner().reads.get(), 1);
+        assert_eq!(first.to_string(), second.to_string());
+
+        // missing ids are not cached
+        assert!(log.get_by_id(9).unwrap().is_none());
+        assert!(log.get_by_id(9).unwrap().is_none());
+        assert_eq!(log.inner().reads.get(), 3);
+    }
+
+    #[test]
+    fn evicts_least_recently_used() {
+        let log = CachingLog::new(counting_log(5), 2);
+        log.get_by_id(1).unwrap();
+        log.get_by_id(2).unwrap();
+        log.get_by_id(1).unwrap();
+        log.get_by_id(3).unwrap();
+        assert_eq!(log.cached(), 2);
+        assert_eq!(log.inner().reads.get(), 3);
+
+        // 1 was used more recently than 2, so 2 was dropped
+        log.get_by_id(1).unwrap();
+        assert_eq!(log.inner().reads.get(), 3);
+        log.get_by_id(2).unwrap();
+        assert_eq!(log.inner().reads.get(), 4);
+
+        let uncached = CachingLog::new(counting_log(1), 0);
+        uncached.get_by_id(1).unwrap();
+        uncached.get_by_id(1).unwrap();
+        assert_eq!(uncached.inner().reads.get(), 2);
+        assert_eq!(uncached.cached(), 0);
+    }
+
+    #[test]
+    fn cleared_by_rewrites() {
+        let path = env::temp_dir()
+            .join(format!("tx_caching_log_{}.txt", process::id()));
+        let _ = fs::remove_file(&path);
+        let time = |t: &str| t.parse::<TransactionTime>().unwrap();
+        let mut log = CachingLog::new(DualLog::load(&path).unwrap(), 4);
+        for day in &["01", "02", "03"] {
+            let data = TransactionData::new(1, 2, "cached").unwrap();
+            let ts = time(&format!("{}1017-10:00:00", day));
+            log.create(data, Some(ts)).unwrap();
+        }
+        let before = log.get_by_id(3).unwrap().unwrap();
+        assert_eq!(log.cached(), 1);
+
+        // pruning re-anchors the chain, so the cached hash is stale
+        assert_eq!(log.prune_older_than(&time("021017-00:00:00")).unwrap(), 1);
+        assert_eq!(log.cached(), 0);
+        let after = log.get_by_id(3).unwrap().unwrap();
+        assert_ne!(before.hash().as_slice(), after.hash().as_slice());
+
+        let mut backup = path.clone().into_os_string();
+        backup.push(".pruned");
+        fs::remove_file(&backup).unwrap();
+        fs::remove_file(&path).unwrap();
+        assert!(log.reset_if_missing());
+        assert_eq!(log.cached(), 0);
+        assert!(log.get_by_id(3).unwrap().is_none());
+    }
+}
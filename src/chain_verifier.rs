@@ -0,0 +1,121 @@
+use std::path::Path;
+use std::sync::{Arc, Mutex, MutexGuard};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::thread;
+use std::time::Duration;
+
+use crate::transaction_log::{FileError, GetAll, SimpleFileLog};
+
+/// Result of the last full verification of a log's chain, shared between
+/// the verifier thread and the routes reporting it.
+#[derive(Debug, Default)]
+pub struct ChainStatus {
+    failure: Mutex<Option<String>>,
+    checks: AtomicUsize,
+}
+
+impl ChainStatus {
+    pub fn new() -> Self {
+        ChainStatus::default()
+    }
+
+    /// Whether the last verification passed, or none ran yet.
+    pub fn is_ok(&self) -> bool {
+        self.failure().is_none()
+    }
+
+    /// Why the last verification failed.
+    pub fn failure(&self) -> Option<String> {
+        self.lock().clone()
+    }
+
+    /// Number of completed verifications.
+    pub fn checks(&self) -> usize {
+        self.checks.load(Ordering::SeqCst)
+    }
+
+    fn record(&self, failure: Option<String>) {
+        *self.lock() = failure;
+        self.checks.fetch_add(1, Ordering::SeqCst);
+    }
+
+    fn lock(&self) -> MutexGuard<Option<String>> {
+        self.failure.lock().unwrap_or_else(|poisoned| poisoned.into_inner())
+    }
+}
+
+/// Reads and verifies the whole log at `path` once, recording the outcome
+/// in `status`.
+pub fn verify_once<P: AsRef<Path>>(path: P, status: &ChainStatus) {
+    let failure = match SimpleFileLog::read_only(path).get_all() {
+        Ok(_) => None,
+        Err(FileError::Verify(e)) => Some(format!(
+            "chain broken at transaction {}: {:?}",
+            e.id(),
+            e
+        )),
+        Err(e) => Some(format!("log unreadable: {:?}", e)),
+    };
+    if let Some(ref failure) = failure {
+        println!("Chain verification failed, {}", failure);
+    }
+    status.record(failure);
+}
+
+/// Verifies the log at `path` right away and then every `interval`, for as
+/// long as the process runs.
+pub fn spawn<P>(path: P, interval: Duration, status: Arc<ChainStatus>)
+where
+    P: AsRef<Path> + Send + 'static,
+{
+    thread::spawn(move || loop {
+        verify_once(&path, &status);
+        thread::sleep(interval);
+    });
+}
+
+
+#[cfg(test)]
+mod test {
+
+    use super::*;
+    use std::env;
+    use std::fs::{self, File};
+    use std::io::{Read, Write};
+    use std::process;
+    use crate::transaction::TransactionData;
+    use crate::transaction_log::TransactionLog;
+
+    #[test]
+    fn corruption_flips_status() {
+        let path = env::temp_dir()
+            .join(format!("tx_chain_verifier_{}.txt", process::id()));
+        File::create(&path).unwrap();
+        let mut log = SimpleFileLog::new(&path);
+        for i in 0..3 {
+            let data = TransactionData::new(1, 2, format!("tx {}", i));
+            log.create(data.unwrap(), None).unwrap();
+        }
+        let status = ChainStatus::new();
+        assert!(status.is_ok());
+        verify_once(&path, &status);
+        assert!(status.is_ok());
+        assert_eq!(status.checks(), 1);
+
+        let mut contents = String::new();
+        File::open(&path)
+            .unwrap()
+            .read_to_string(&mut contents)
+            .unwrap();
+        File::create(&path)
+            .unwrap()
+            .write_all(contents.replace("tx 1", "tx X").as_bytes())
+            .unwrap();
+        verify_once(&path, &status);
+        assert!(!status.is_ok());
+        let failure = status.failure().unwrap();
+        assert!(failure.contains("transaction 2"), "{}", failure);
+        assert_eq!(status.checks(), 2);
+        fs::remove_file(&path).unwrap();
+    }
+}
@@ -0,0 +1,86 @@
+use std::cell::RefCell;
+use std::fmt;
+use std::mem;
+
+use chrono::prelude::*;
+
+/// Source of the current time for `TransactionTime::current`.
+pub trait Clock: fmt::Debug {
+    fn now(&self) -> DateTime<Utc>;
+}
+
+/// The real time of the system.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct SystemClock;
+
+impl Clock for SystemClock {
+    fn now(&self) -> DateTime<Utc> {
+        Utc::now()
+    }
+}
+
+/// Always the same time, to get reproducible transactions in tests.
+#[derive(Debug, Clone, Copy)]
+pub struct FixedClock(pub DateTime<Utc>);
+
+impl Clock for FixedClock {
+    fn now(&self) -> DateTime<Utc> {
+        self.0
+    }
+}
+
+thread_local! {
+    static CLOCK: RefCell<Box<dyn Clock>> = RefCell::new(Box::new(SystemClock));
+}
+
+/// Current time of the clock of this thread.
+pub fn now() -> DateTime<Utc> {
+    CLOCK.with(|clock| clock.borrow().now())
+}
+
+/// Replaces the clock of this thread, returning the previous one.
+pub fn set_clock(clock: Box<dyn Clock>) -> Box<dyn Clock> {
+    CLOCK.with(|current| mem::replace(&mut *current.borrow_mut(), clock))
+}
+
+/// Runs `f` with `clock` as the clock of this thread.
+pub fn with_clock<C, F, R>(clock: C, f: F) -> R
+where
+    C: Clock + 'static,
+    F: FnOnce() -> R,
+{
+    let previous = set_clock(Box::new(clock));
+    let result = f();
+    set_clock(previous);
+    result
+}
+
+
+#[cfg(test)]
+mod test {
+
+    use super::*;
+    use crate::transaction::*;
+    use crate::transaction_log::{FullTransactionLog, TransactionLog};
+
+    #[test]
+    fn fixed_clock_gives_known_hash() {
+        let time = Utc.ymd(2017, 10, 4).and_hms(9, 0, 0);
+        let tx = with_clock(FixedClock(time), || {
+            let mut log = FullTransactionLog::new();
+            log.create(TransactionData::new(1, 2, "clocked").unwrap(), None)
+                .unwrap()
+        });
+        assert_eq!(tx.ts().to_string(), "041017-10:00:00");
+        let expected = Transaction::new(
+            TransactionId::new(1).unwrap(),
+            "041017-10:00:00".parse().unwrap(),
+            TransactionData::new(1, 2, "clocked").unwrap(),
+            None,
+        );
+        assert_eq!(tx.hash().to_string(), expected.hash().to_string());
+
+        // the system clock is back afterwards
+        assert!(now() > time);
+    }
+}
@@ -0,0 +1,69 @@
+use std::fmt::Write;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum HexError {
+    /// A character which is not a hex digit.
+    InvalidChar(char),
+    /// An odd number of hex digits.
+    OddLength,
+}
+
+/// Two uppercase hex digits per byte.
+pub fn encode_upper(bytes: &[u8]) -> String {
+    let mut s = String::with_capacity(bytes.len() * 2);
+    for b in bytes {
+        write!(s, "{:02X}", b).expect("writing to a String can't fail");
+    }
+    s
+}
+
+/// Decodes pairs of hex digits of either case, from left to right.
+pub fn decode(s: &str) -> Result<Vec<u8>, HexError> {
+    let mut bytes = Vec::with_capacity(s.len() / 2);
+    let mut chars = s.chars();
+    loop {
+        match (chars.next(), chars.next()) {
+            (Some(c1), Some(c2)) => {
+                bytes.push((digit(c1)? << 4) | digit(c2)?);
+            }
+            (Some(_), None) => return Err(HexError::OddLength),
+            (None, _) => return Ok(bytes),
+        }
+    }
+}
+
+fn digit(c: char) -> Result<u8, HexError> {
+    c.to_digit(16)
+        .map(|d| d as u8)
+        .ok_or(HexError::InvalidChar(c))
+}
+
+
+#[cfg(test)]
+mod test {
+
+    use super::*;
+
+    #[test]
+    fn empty() {
+        assert_eq!(encode_upper(&[]), "");
+        assert_eq!(decode(""), Ok(Vec::new()));
+    }
+
+    #[test]
+    fn invalid() {
+        assert_eq!(decode("ABC"), Err(HexError::OddLength));
+        assert_eq!(decode("0G"), Err(HexError::InvalidChar('G')));
+        assert_eq!(decode("+F"), Err(HexError::InvalidChar('+')));
+    }
+
+    #[test]
+    fn round_trip() {
+        let bytes: Vec<u8> = (0..32).map(|i| i * 8 + 1).collect();
+        let hex = encode_upper(&bytes);
+        assert_eq!(hex.len(), 64);
+        assert!(hex.starts_with("0109111921"));
+        assert_eq!(decode(&hex), Ok(bytes.clone()));
+        assert_eq!(decode(&hex.to_lowercase()), Ok(bytes));
+    }
+}
@@ -0,0 +1,126 @@
+use std::fmt;
+
+use crate::transaction::{Error, TransactionId};
+
+/// Strategy choosing the id of the next transaction.
+pub trait IdAllocator: fmt::Debug + Send + Sync {
+    /// Id following `last`, or the first id of an empty log.
+    fn next(
+        &self,
+        last: Option<TransactionId>,
+    ) -> Result<TransactionId, Error>;
+
+    /// Whether `id` is allowed to directly follow `prev` in a log.
+    fn follows(&self, prev: TransactionId, id: TransactionId) -> bool {
+        self.next(Some(prev)).ok() == Some(id)
+    }
+}
+
+/// Consecutive ids starting at `TransactionId::MIN_ID`.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct SequentialAllocator;
+
+impl IdAllocator for SequentialAllocator {
+    fn next(
+        &self,
+        last: Option<TransactionId>,
+    ) -> Result<TransactionId, Error> {
+        Ok(last.map(|id| id.next()).unwrap_or_default())
+    }
+}
+
+/// Ids `offset`, `offset + stride`, `offset + 2 * stride`, ... so that
+/// `stride` nodes with different offsets never allocate the same id.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct StridedAllocator {
+    offset: u32,
+    stride: u32,
+}
+
+impl StridedAllocator {
+    /// `offset` has to be in `1..=stride`.
+    pub fn new(offset: u32, stride: u32) -> Result<Self, Error> {
+        if stride == 0 || offset < TransactionId::MIN_ID || offset > stride {
+            return Err(Error::IllegalArgument(format!(
+                "Invalid id offset {} for stride {}",
+                offset,
+                stride
+            )));
+        }
+        Ok(StridedAllocator { offset, stride })
+    }
+
+    pub fn offset(&self) -> u32 {
+        self.offset
+    }
+
+    pub fn stride(&self) -> u32 {
+        self.stride
+    }
+}
+
+impl IdAllocator for StridedAllocator {
+    fn next(
+        &self,
+        last: Option<TransactionId>,
+    ) -> Result<TransactionId, Error> {
+        let last = match last {
+            Some(last) => last.inner(),
+            None => return TransactionId::new(self.offset),
+        };
+        // smallest id above `last` in the residue class of `offset`
+        let rem = (self.offset % self.stride + self.stride
+            - last % self.stride) % self.stride;
+        let step = if rem == 0 { self.stride } else { rem };
+        let next = last.checked_add(step).ok_or_else(|| {
+            Error::IllegalArgument(format!("No id left after {}", last))
+        })?;
+        TransactionId::new(next)
+    }
+}
+
+
+#[cfg(test)]
+mod test {
+
+    use super::*;
+
+    fn id(id: u32) -> TransactionId {
+        TransactionId::new(id).unwrap()
+    }
+
+    #[test]
+    fn sequential() {
+        let alloc = SequentialAllocator;
+        assert_eq!(alloc.next(None), Ok(id(1)));
+        assert_eq!(alloc.next(Some(id(41))), Ok(id(42)));
+        assert!(alloc.follows(id(1), id(2)));
+        assert!(!alloc.follows(id(1), id(3)));
+    }
+
+    #[test]
+    fn strided() {
+        let alloc = StridedAllocator::new(2, 3).unwrap();
+        let mut ids = Vec::new();
+        let mut last = None;
+        for _ in 0..4 {
+            let next = alloc.next(last).unwrap();
+            ids.push(next.inner());
+            last = Some(next);
+        }
+        assert_eq!(ids, vec![2, 5, 8, 11]);
+        // continues after an id allocated by another node
+        assert_eq!(alloc.next(Some(id(6))), Ok(id(8)));
+        assert_eq!(alloc.next(Some(id(7))), Ok(id(8)));
+        assert!(alloc.follows(id(5), id(8)));
+        assert!(!alloc.follows(id(5), id(6)));
+
+        let last_node = StridedAllocator::new(3, 3).unwrap();
+        assert_eq!(last_node.next(Some(id(3))), Ok(id(6)));
+        assert!(last_node.next(Some(id(TransactionId::MAX_ID))).is_err());
+
+        assert!(StridedAllocator::new(0, 3).is_err());
+        assert!(StridedAllocator::new(4, 3).is_err());
+        assert!(StridedAllocator::new(1, 0).is_err());
+    }
+}
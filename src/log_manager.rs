@@ -0,0 +1,239 @@
+use std::collections::HashMap;
+use std::fs::{self, OpenOptions};
+use std::path::PathBuf;
+use std::sync::{Arc, Mutex, MutexGuard, RwLock};
+
+use crate::broadcast::Broadcast;
+use crate::transaction::LogFormat;
+use crate::transaction_log::{DualLog, FileError, SimpleFileLog};
+
+/// A named log, shared by its readers and held exclusively by writers.
+pub type SharedLog = Arc<RwLock<DualLog<PathBuf>>>;
+
+/// Independent transaction logs kept as `<dir>/<name>.txt`, each with its
+/// own chain and ids. Logs are loaded on first use and publish their
+/// transactions to their own `Broadcast`.
+#[derive(Debug)]
+pub struct LogManager {
+    dir: PathBuf,
+    reject_duplicates: bool,
+    format: LogFormat,
+    max_transactions: Option<usize>,
+    max_bytes: Option<u64>,
+    logs: Mutex<HashMap<String, SharedLog>>,
+}
+
+impl LogManager {
+    pub fn new<P: Into<PathBuf>>(dir: P) -> Self {
+        LogManager {
+            dir: dir.into(),
+            reject_duplicates: false,
+            format: LogFormat::default(),
+            max_transactions: None,
+            max_bytes: None,
+            logs: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// Rejects duplicates in every log, see `DualLog::with_reject_duplicates`.
+    pub fn with_reject_duplicates(mut self, reject_duplicates: bool) -> Self {
+        self.reject_duplicates = reject_duplicates;
+        self
+    }
+
+    /// Format of new logs, see `SimpleFileLog::with_format`.
+    pub fn with_format(mut self, format: LogFormat) -> Self {
+        self.format = format;
+        self
+    }
+
+    /// Limits every log, see `DualLog::with_max_transactions`.
+    pub fn with_max_transactions(mut self, max: usize) -> Self {
+        self.max_transactions = Some(max);
+        self
+    }
+
+    /// Limits every log, see `DualLog::with_max_bytes`.
+    pub fn with_max_bytes(mut self, max: u64) -> Self {
+        self.max_bytes = Some(max);
+        self
+    }
+
+    /// Names may only use ASCII letters, digits, `-` and `_`, so they are
+    /// safe to use as file names.
+    pub fn valid_name(name: &str) -> bool {
+        !name.is_empty() && name.len() <= 64
+            && name.chars().all(|c| {
+                c.is_ascii_alphanumeric() || c == '-' || c == '_'
+            })
+    }
+
+    fn path(&self, name: &str) -> PathBuf {
+        self.dir.join(format!("{}.txt", name))
+    }
+
+    /// The log `name`, or `None` if it was never created or its file was
+    /// deleted.
+    pub fn get(&self, name: &str) -> Result<Option<SharedLog>, FileError> {
+        self.load(name, false)
+    }
+
+    /// The log `name`, creating its file if it doesn't exist yet.
+    pub fn get_or_create(&self, name: &str) -> Result<SharedLog, FileError> {
+        self.load(name, true).map(|log| log.expect("log was created"))
+    }
+
+    fn load(
+        &self,
+        name: &str,
+        create: bool,
+    ) -> Result<Option<SharedLog>, FileError> {
+        if !Self::valid_name(name) {
+            return Err(FileError::Other(
+                format!("Invalid log name `{}`", name),
+            ));
+        }
+        let mut logs = self.lock();
+        let path = self.path(name);
+        if let Some(log) = logs.get(name) {
+            // a deleted file is reset by the next write, see
+            // `DualLog::reset_if_missing`
+            if create || path.exists() {
+                return Ok(Some(log.clone()));
+            }
+            return Ok(None);
+        }
+        if !path.exists() {
+            if !create {
+                return Ok(None);
+            }
+            fs::create_dir_all(&self.dir)?;
+            OpenOptions::new().append(true).create(true).open(&path)?;
+        }
+        let file_log = SimpleFileLog::new(path).with_format(self.format);
+        let mut log = DualLog::load_from(file_log)?
+            .with_reject_duplicates(self.reject_duplicates)
+            .with_broadcast(Arc::new(Broadcast::new()));
+        if let Some(max) = self.max_transactions {
+            log = log.with_max_transactions(max);
+        }
+        if let Some(max) = self.max_bytes {
+            log = log.with_max_bytes(max);
+        }
+        let log = Arc::new(RwLock::new(log));
+        logs.insert(name.to_owned(), log.clone());
+        Ok(Some(log))
+    }
+
+    fn lock(&self) -> MutexGuard<'_, HashMap<String, SharedLog>> {
+        self.logs.lock().unwrap_or_else(|poisoned| poisoned.into_inner())
+    }
+}
+
+
+#[cfg(test)]
+mod test {
+
+    use super::*;
+    use std::env;
+    use std::process;
+    use crate::transaction::TransactionData;
+    use crate::transaction_log::{GetAll, TransactionLog};
+
+    fn temp_dir(name: &str) -> PathBuf {
+        let dir = env::temp_dir()
+            .join(format!("tx_log_manager_{}_{}", name, process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        dir
+    }
+
+    fn create(manager: &LogManager, name: &str, text: &str) -> u32 {
+        let log = manager.get_or_create(name).unwrap();
+        let mut log = log.write().unwrap();
+        let data = TransactionData::new(1, 2, text).unwrap();
+        log.create(data, None).unwrap().id().inner()
+    }
+
+    #[test]
+    fn independent_logs() {
+        let dir = temp_dir("independent");
+        let manager = LogManager::new(&dir);
+        assert!(manager.get("a").unwrap().is_none());
+        assert_eq!(create(&manager, "a", "first a"), 1);
+        assert_eq!(create(&manager, "a", "second a"), 2);
+        assert_eq!(create(&manager, "b", "first b"), 1);
+
+        let a = manager.get("a").unwrap().unwrap();
+        let b = manager.get("b").unwrap().unwrap();
+        let a = a.read().unwrap().get_all().unwrap();
+        let b = b.read().unwrap().get_all().unwrap();
+        assert_eq!(a.len(), 2);
+        assert_eq!(b.len(), 1);
+        assert_ne!(a[0].hash().to_string(), b[0].hash().to_string());
+
+        // a new manager loads the existing files
+        let reopened = LogManager::new(&dir);
+        let a = reopened.get("a").unwrap().unwrap();
+        assert_eq!(a.read().unwrap().next_id().unwrap().inner(), 3);
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn readers_share_a_log() {
+        let dir = temp_dir("readers");
+        let manager = LogManager::new(&dir);
+        create(&manager, "a", "first a");
+        let log = manager.get("a").unwrap().unwrap();
+        let reader = log.read().unwrap();
+        {
+            let other = manager.get("a").unwrap().unwrap();
+            let other = other.try_read().expect("readers overlap");
+            assert_eq!(other.get_all().unwrap().len(), 1);
+            assert!(log.try_write().is_err());
+        }
+        assert!(log.try_write().is_err());
+        drop(reader);
+        assert!(log.try_write().is_ok());
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn logs_get_the_settings() {
+        let dir = temp_dir("settings");
+        let manager = LogManager::new(&dir).with_max_transactions(1);
+        let log = manager.get_or_create("a").unwrap();
+        let mut rx = log.read().unwrap().broadcast().unwrap().subscribe();
+        assert_eq!(create(&manager, "a", "first a"), 1);
+        assert!(rx.try_recv().unwrap().data().text() == "first a");
+
+        let data = TransactionData::new(1, 2, "second a").unwrap();
+        assert!(log.write().unwrap().create(data, None).is_err());
+        // every log has its own quota and broadcast
+        assert_eq!(create(&manager, "b", "first b"), 1);
+        assert!(rx.try_recv().is_err());
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn deleted_file() {
+        let dir = temp_dir("deleted");
+        let manager = LogManager::new(&dir);
+        create(&manager, "a", "first a");
+        fs::remove_file(dir.join("a.txt")).unwrap();
+        assert!(manager.get("a").unwrap().is_none());
+        assert_eq!(create(&manager, "a", "new a"), 1);
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn invalid_names() {
+        let dir = temp_dir("invalid");
+        let manager = LogManager::new(&dir);
+        for name in &["", "../etc", "a/b", "a.txt", "with space"] {
+            assert!(!LogManager::valid_name(name));
+            assert!(manager.get_or_create(name).is_err());
+        }
+        assert!(LogManager::valid_name("tenant-1_a"));
+        assert!(!dir.exists());
+    }
+}
@@ -1,33 +1,344 @@
-#![feature(plugin)]
-#![plugin(rocket_codegen)]
 extern crate chrono;
+#[macro_use]
 extern crate rocket;
-//extern crate clap;
+extern crate clap;
+#[cfg(feature = "flate2")]
+extern crate flate2;
 extern crate itertools;
 #[macro_use]
 extern crate quick_error;
+extern crate serde;
+#[macro_use]
+extern crate serde_derive;
+#[macro_use]
+extern crate serde_json;
 extern crate sha2;
 
+mod api_error;
+mod broadcast;
+mod caching_log;
+mod chain_verifier;
+mod clock;
+mod hex;
+mod id_allocator;
+mod log_manager;
+mod openapi;
+mod pipe;
 mod transaction;
 mod transaction_log;
 
-use std::fs::OpenOptions;
-use std::sync::Mutex;
+use std::env;
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, Read};
+use std::net::{IpAddr, ToSocketAddrs};
+use std::path::{Path, PathBuf};
+use std::process;
+use std::str::FromStr;
+use std::sync::{Arc, PoisonError, RwLock, RwLockReadGuard, RwLockWriteGuard};
+use std::thread;
+use std::time::Duration;
+
+use clap::{App, Arg, SubCommand};
+use serde::de::DeserializeOwned;
 
-use rocket::response::status;
-use rocket::State;
+use rocket::config::Config;
+use rocket::data::ToByteUnit;
+use rocket::fairing::AdHoc;
+use rocket::figment::providers::{Env, Format, Toml};
+use rocket::figment::{Figment, Profile};
+use rocket::outcome::Outcome;
+use rocket::request::{self, FromRequest, Request};
+use rocket::response::stream::ByteStream;
+use rocket::response::{self, content, status, Responder, Response};
+use rocket::serde::json::Json;
+use rocket::tokio::sync::mpsc;
+use rocket::tokio::task;
+use rocket::{Data, State};
 use rocket::http;
 
-use transaction::{TransactionData, TransactionTime};
+use api_error::{ApiError, RouteError};
+use broadcast::{Broadcast, EventStream};
+use caching_log::CachingLog;
+use chain_verifier::ChainStatus;
+use log_manager::{LogManager, SharedLog};
+use transaction::{gid_counts, merkle_root, CharPolicy, DataFormat, Gid,
+                  LogFormat, TimeFormat, Transaction, TransactionData,
+                  TransactionHash, TransactionId, TransactionTime};
 use transaction_log::*;
 
+/// The served log. Reads share it, while every write holds it exclusively,
+/// so writes are serialized no matter how many workers serve requests.
+/// Reads by id go through a cache of `SettingsState::cache_size`
+/// transactions.
+#[derive(Debug)]
+struct TransactionLogState(RwLock<CachingLog<DualLog<String>>>);
+
+fn recover_poisoned<G>(poisoned: PoisonError<G>) -> G {
+    eprintln!("Warning: recovering poisoned transaction log lock");
+    poisoned.into_inner()
+}
+
+/// Locks `log` for writing, recovering it if a panicking thread poisoned
+/// the lock. Every append is a single write to the file, so the log stays
+/// valid. A deleted file empties the log instead of failing every request.
+fn write_lock<P: AsRef<Path>>(
+    log: &RwLock<DualLog<P>>,
+) -> RwLockWriteGuard<'_, DualLog<P>> {
+    let mut guard = log.write().unwrap_or_else(recover_poisoned);
+    guard.reset_if_missing();
+    guard
+}
+
+/// Locks `log` for reading, like `write_lock` but shared with other
+/// readers.
+fn read_lock<P: AsRef<Path>>(
+    log: &RwLock<DualLog<P>>,
+) -> RwLockReadGuard<'_, DualLog<P>> {
+    let guard = log.read().unwrap_or_else(recover_poisoned);
+    if !guard.lost_file() {
+        return guard;
+    }
+    // forgetting the transactions of a deleted file is a write
+    drop(guard);
+    drop(write_lock(log));
+    log.read().unwrap_or_else(recover_poisoned)
+}
+
+impl TransactionLogState {
+    fn new(log: DualLog<String>, cache_size: usize) -> Self {
+        TransactionLogState(RwLock::new(CachingLog::new(log, cache_size)))
+    }
+
+    /// Locks the log for writing, like `write_lock` but also clearing the
+    /// cache of a deleted file.
+    fn write(&self) -> RwLockWriteGuard<'_, CachingLog<DualLog<String>>> {
+        let mut log = self.0.write().unwrap_or_else(recover_poisoned);
+        log.reset_if_missing();
+        log
+    }
+
+    /// Locks the log for reading, like `read_lock`.
+    fn read(&self) -> RwLockReadGuard<'_, CachingLog<DualLog<String>>> {
+        let log = self.0.read().unwrap_or_else(recover_poisoned);
+        if !log.lost_file() {
+            return log;
+        }
+        drop(log);
+        drop(self.write());
+        self.0.read().unwrap_or_else(recover_poisoned)
+    }
+}
+
+#[derive(Debug)]
+struct BroadcastState(Arc<Broadcast>);
+
 #[derive(Debug)]
-struct TransactionLogState(Mutex<DualLog<String>>);
+struct ChainStatusState(Arc<ChainStatus>);
 
 #[derive(Debug, Clone)]
 struct SettingsState {
     pub base_url: String,
     pub tx_log_file: String,
+    /// Directory of the logs served below `/<log_name>/transactions`.
+    pub log_dir: String,
+    pub name: Option<String>,
+    pub max_body_size: u64,
+    pub reject_duplicates: bool,
+    /// Limits of the log, writes beyond them get 507.
+    pub max_transactions: Option<usize>,
+    pub max_log_bytes: Option<u64>,
+    /// Seconds between background verifications of the whole chain.
+    pub verify_interval: Option<u64>,
+    /// Number of transactions read by id to keep in memory, none for 0.
+    pub cache_size: usize,
+    /// Whether the `/admin` routes are served.
+    pub enable_admin: bool,
+    /// Format of the data created from JSON, advertised in the
+    /// `X-Transaction-Format` header.
+    pub format: DataFormat,
+    /// Characters allowed in the text of new data, and the policy of new
+    /// logs. With `Escaped`, advertised as `+escaped` in the format header,
+    /// any text is stored escaped.
+    pub policy: CharPolicy,
+    /// Time format of new logs, which the timestamps of requests use too.
+    pub time_format: TimeFormat,
+    /// Address to listen on, instead of the one in `Rocket.toml`.
+    pub address: Option<String>,
+    /// Port to listen on, instead of the one in `Rocket.toml`.
+    pub port: Option<u16>,
+    /// PEM certificate chain and private key to serve HTTPS with.
+    pub tls_cert: Option<String>,
+    pub tls_key: Option<String>,
+    /// Rocket environment, instead of `ROCKET_ENV`.
+    pub env: Option<Environment>,
+    /// Origin allowed to call the API from a browser, `*` for any. Without
+    /// it no CORS headers are sent and preflight requests get 404.
+    pub cors_origin: Option<String>,
+    /// Number of threads serving requests, instead of the one in
+    /// `Rocket.toml`. More workers only help reads, see
+    /// `TransactionLogState`.
+    pub workers: Option<u16>,
+}
+
+impl SettingsState {
+    /// Absolute URL of `path` below `base_url`, with exactly one slash
+    /// between them.
+    fn url(&self, path: &str) -> String {
+        format!(
+            "{}/{}",
+            self.base_url.trim_end_matches('/'),
+            path.trim_start_matches('/')
+        )
+    }
+
+    /// Format of the logs, see `SimpleFileLog::with_format`.
+    fn log_format(&self) -> LogFormat {
+        LogFormat {
+            time: self.time_format,
+            policy: self.policy,
+        }
+    }
+
+    /// Parses the timestamp `ts` of a request.
+    fn parse_time(&self, ts: &str) -> Result<TransactionTime, RouteError> {
+        TransactionTime::parse_with_format(ts, self.time_format)
+            .map_err(|e| RouteError::Invalid("bad_timestamp", e))
+    }
+}
+
+/// Whether `address` is an IP address or a host name.
+fn valid_address(address: &str) -> bool {
+    if address.parse::<IpAddr>().is_ok() {
+        return true;
+    }
+    !address.is_empty() && address.len() <= 253
+        && address.split('.').all(|label| {
+            !label.is_empty() && label.len() <= 63 && !label.starts_with('-')
+                && !label.ends_with('-')
+                && label.chars().all(|c| c.is_ascii_alphanumeric() || c == '-')
+        })
+}
+
+/// Rocket environment, selecting the profile of `Rocket.toml` like before
+/// Rocket 0.5 replaced environments by profiles.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Environment {
+    Development,
+    Staging,
+    Production,
+}
+
+impl Environment {
+    /// Variable selecting the environment when `--env` isn't given.
+    const VAR: &'static str = "ROCKET_ENV";
+
+    /// The environment of `ROCKET_ENV`, development if it isn't set.
+    fn active() -> Result<Environment, String> {
+        match env::var(Self::VAR) {
+            Ok(name) => name.parse().map_err(|_| {
+                format!("Unknown environment `{}` in {}", name, Self::VAR)
+            }),
+            Err(_) => Ok(Environment::Development),
+        }
+    }
+
+    /// Profile of the environment in `Rocket.toml`.
+    fn profile(self) -> Profile {
+        match self {
+            Environment::Development => Config::DEBUG_PROFILE,
+            Environment::Staging => Profile::new("staging"),
+            Environment::Production => Config::RELEASE_PROFILE,
+        }
+    }
+
+    /// Defaults of the environment, production only logs critical messages.
+    fn defaults(self) -> Config {
+        match self {
+            Environment::Production => Config::release_default(),
+            _ => Config::debug_default(),
+        }
+    }
+}
+
+impl FromStr for Environment {
+    type Err = ();
+
+    fn from_str(s: &str) -> Result<Self, ()> {
+        match s {
+            "dev" | "development" => Ok(Environment::Development),
+            "stage" | "staging" => Ok(Environment::Staging),
+            "prod" | "production" => Ok(Environment::Production),
+            _ => Err(()),
+        }
+    }
+}
+
+/// The IP address `address` resolves to, Rocket only listens on those.
+fn resolve(address: &str) -> Result<IpAddr, String> {
+    (address, 0)
+        .to_socket_addrs()
+        .ok()
+        .and_then(|mut addrs| addrs.next())
+        .map(|addr| addr.ip())
+        .ok_or_else(|| format!("Cannot resolve address `{}`", address))
+}
+
+/// Fails unless `path` is a file which can be opened for reading.
+fn check_readable(what: &str, path: &str) -> Result<(), String> {
+    let is_file = File::open(path)
+        .and_then(|file| file.metadata())
+        .map(|meta| meta.is_file())
+        .map_err(|e| format!("Cannot read {} `{}`: {}", what, path, e))?;
+    if !is_file {
+        return Err(format!("Cannot read {} `{}`: not a file", what, path));
+    }
+    Ok(())
+}
+
+/// Rocket configuration of `Rocket.toml` for the environment of `settings`,
+/// overriding only the address, port, TLS files and workers given there.
+fn listen_config(settings: &SettingsState) -> Result<Figment, String> {
+    let tls = match (&settings.tls_cert, &settings.tls_key) {
+        (&Some(ref cert), &Some(ref key)) => {
+            check_readable("TLS certificate", cert)?;
+            check_readable("TLS key", key)?;
+            Some((cert.clone(), key.clone()))
+        }
+        (&None, &None) => None,
+        _ => {
+            return Err(
+                "TLS needs both a certificate and a key".to_owned(),
+            )
+        }
+    };
+    let env = match settings.env {
+        Some(env) => env,
+        None => Environment::active()?,
+    };
+    // the providers of `Config::figment`, with the defaults of `env`
+    let toml = Toml::file(Env::var_or("ROCKET_CONFIG", "Rocket.toml"));
+    let mut config = Figment::from(env.defaults())
+        .merge(toml.nested())
+        .merge(Env::prefixed("ROCKET_").ignore(&["PROFILE"]).global())
+        .select(env.profile());
+    if let Some(ref address) = settings.address {
+        if !valid_address(address) {
+            return Err(format!("Invalid address `{}`", address));
+        }
+        config = config.merge(("address", resolve(address)?));
+    }
+    if let Some(port) = settings.port {
+        config = config.merge(("port", port));
+    }
+    if let Some((cert, key)) = tls {
+        config = config.merge(("tls.certs", cert)).merge(("tls.key", key));
+    }
+    if let Some(workers) = settings.workers {
+        config = config.merge(("workers", workers));
+    }
+    Config::try_from(&config)
+        .map_err(|e| format!("Invalid listen config: {}", e))?;
+    Ok(config)
 }
 
 impl Default for SettingsState {
@@ -35,124 +346,2395 @@ impl Default for SettingsState {
         SettingsState {
             base_url: "http://localhost".into(),
             tx_log_file: "/tmp/tx_log.txt".into(),
+            log_dir: "/tmp/tx_logs".into(),
+            name: None,
+            max_body_size: 64 * 1024,
+            reject_duplicates: false,
+            max_transactions: None,
+            max_log_bytes: None,
+            verify_interval: None,
+            cache_size: 0,
+            enable_admin: false,
+            format: DataFormat::default(),
+            policy: CharPolicy::default(),
+            time_format: TimeFormat::default(),
+            address: None,
+            port: None,
+            tls_cert: None,
+            tls_key: None,
+            env: None,
+            cors_origin: None,
+            workers: None,
         }
     }
 }
 
 #[get("/")]
 fn read_all_transactions(
-    tx_log: State<TransactionLogState>,
-) -> Result<String, http::Status> {
+    tx_log: &State<TransactionLogState>,
+) -> Result<String, RouteError> {
     Ok(itertools::join(
-        tx_log
-            .0
-            .lock()
-            .map_err(|_| http::Status::InternalServerError)?
-            .get_all()
-            .map_err(|_| http::Status::InternalServerError)?
-            .iter()
-            .map(|t| t.to_string()),
+        tx_log.read().get_all()?.iter().map(|t| t.to_string()),
         "\n",
     ))
 }
 
 #[get("/last")]
 fn read_last_transaction(
-    tx_log: State<TransactionLogState>,
-) -> Result<Option<String>, http::Status> {
-    Ok(
-        tx_log
-            .0
-            .lock()
-            .map_err(|_| http::Status::InternalServerError)?
-            .last()
-            .map_err(|_| http::Status::InternalServerError)?
-            .map(|t| t.to_string()),
-    )
+    tx_log: &State<TransactionLogState>,
+) -> Result<Option<String>, RouteError> {
+    Ok(tx_log.read().last()?.map(|t| t.to_string()))
+}
+
+#[derive(Debug, FromForm)]
+struct LastQuery {
+    wait: u64,
+    after: u32,
+}
+
+/// Longest `wait` accepted by `/last`, in milliseconds.
+const MAX_WAIT_MS: u64 = 30_000;
+
+/// Long poll: while the last transaction is still `after`, or the log is
+/// empty and `after` is 0, waits up to `wait` ms for the next one and
+/// answers 204 if none comes.
+// example: $ curl "http://localhost:8000/transactions/last?wait=5000&after=7"
+#[get("/last?<query..>")]
+async fn wait_last_transaction(
+    query: LastQuery,
+    tx_log: &State<TransactionLogState>,
+    broadcast: &State<BroadcastState>,
+) -> Result<Option<status::Custom<String>>, RouteError> {
+    let found = |last: Option<Transaction>| {
+        last.map(|t| status::Custom(http::Status::Ok, t.to_string()))
+    };
+    let last = tx_log.read().last()?;
+    if last.as_ref().map_or(0, |t| t.id().inner()) != query.after {
+        return Ok(found(last));
+    }
+    let wait = Duration::from_millis(query.wait.min(MAX_WAIT_MS));
+    let broadcast = broadcast.0.clone();
+    // the wait blocks, so it must not hold up a thread serving requests
+    let published =
+        task::spawn_blocking(move || broadcast.wait_after(query.after, wait))
+            .await
+            .unwrap_or(false);
+    if !published {
+        return Ok(Some(status::Custom(http::Status::NoContent, String::new())));
+    }
+    Ok(found(tx_log.read().last()?))
+}
+
+// example: $ curl http://localhost:8000/transactions/root
+#[get("/root")]
+fn read_merkle_root(
+    tx_log: &State<TransactionLogState>,
+) -> Result<String, RouteError> {
+    Ok(merkle_root(tx_log.read().get_all()?).to_string())
+}
+
+/// The log file as stored, for backups. Streamed from disk without parsing
+/// it, so it is exact even for lines other routes would reject.
+// example: $ curl -OJ http://localhost:8000/transactions/export
+#[get("/export")]
+fn export_transactions(
+    tx_log: &State<TransactionLogState>,
+) -> Result<Export<ByteStream![Vec<u8>]>, RouteError> {
+    let file = tx_log.read().export()?;
+    Ok(Export(reader_stream(file)))
+}
+
+/// Response of `/export`, offering the body as a download of the log file.
+struct Export<R>(R);
+
+impl<'r, 'o: 'r, R: Responder<'r, 'o>> Responder<'r, 'o> for Export<R> {
+    fn respond_to(self, req: &'r Request<'_>) -> response::Result<'o> {
+        Response::build_from(self.0.respond_to(req)?)
+            .header(http::ContentType::Plain)
+            .raw_header(
+                "Content-Disposition",
+                r#"attachment; filename="tx_log.txt""#,
+            )
+            .ok()
+    }
+}
+
+/// Streams the body read from `reader`, which may block, on a thread of its
+/// own.
+fn reader_stream<R>(mut reader: R) -> ByteStream![Vec<u8>]
+where
+    R: Read + Send + 'static,
+{
+    let (chunks, mut rx) = mpsc::channel(1);
+    thread::spawn(move || {
+        let mut buf = [0; 4096];
+        // a dropped receiver means the client went away
+        while let Ok(n) = reader.read(&mut buf) {
+            if n == 0 || chunks.blocking_send(buf[..n].to_vec()).is_err() {
+                break;
+            }
+        }
+    });
+    ByteStream! {
+        while let Some(chunk) = rx.recv().await {
+            yield chunk;
+        }
+    }
+}
+
+/// Streams the events of `events` without a thread of its own, so a client
+/// going away drops the subscription with the response.
+fn event_bytes(mut events: EventStream) -> ByteStream![Vec<u8>] {
+    ByteStream! {
+        while let Some(event) = events.next().await {
+            yield event;
+        }
+    }
+}
+
+// example: $ curl http://localhost:8000/transactions/group/5/count
+#[get("/group/<gid>/count")]
+fn read_group_count(
+    gid: String,
+    tx_log: &State<TransactionLogState>,
+) -> Result<String, RouteError> {
+    let gid: Gid = gid.parse()
+        .map_err(|e| RouteError::Invalid("bad_gid", e))?;
+    let all = tx_log.read().get_all()?;
+    Ok(gid_counts(&all).get(&gid).cloned().unwrap_or(0).to_string())
+}
+
+#[derive(Debug, FromForm)]
+struct TailQuery {
+    n: usize,
+}
+
+/// Number of transactions returned by `/tail` without `n`.
+const DEFAULT_TAIL: usize = 10;
+/// Largest `n` accepted by `/tail`.
+const MAX_TAIL: usize = 1000;
+
+fn tail(
+    tx_log: &TransactionLogState,
+    n: usize,
+) -> Result<String, RouteError> {
+    Ok(itertools::join(
+        tx_log.read().tail(n.min(MAX_TAIL))?.iter().map(|t| t.to_string()),
+        "\n",
+    ))
+}
+
+// example: $ curl http://localhost:8000/transactions/tail?n=5
+#[get("/tail")]
+fn read_tail(
+    tx_log: &State<TransactionLogState>,
+) -> Result<String, RouteError> {
+    tail(tx_log, DEFAULT_TAIL)
+}
+
+#[get("/tail?<query..>")]
+fn read_tail_n(
+    query: TailQuery,
+    tx_log: &State<TransactionLogState>,
+) -> Result<String, RouteError> {
+    tail(tx_log, query.n)
 }
 
 #[get("/<id>")]
 fn read_transaction(
     id: u32,
-    tx_log: State<TransactionLogState>,
-) -> Result<Option<String>, http::Status> {
-    Ok(
-        tx_log
-            .0
-            .lock()
-            .map_err(|_| http::Status::InternalServerError)?
-            .get_by_id(id)
-            .map_err(|_| http::Status::InternalServerError)?
+    tx_log: &State<TransactionLogState>,
+) -> Result<Option<String>, RouteError> {
+    Ok(tx_log.read().get_by_id(id)?.map(|t| t.to_string()))
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+struct TransactionProof {
+    transaction: String,
+    prev_hash: Option<String>,
+}
+
+#[derive(Debug, FromForm)]
+struct SinceQuery {
+    ts: String,
+}
+
+// example: $ curl http://localhost:8000/transactions/since?ts=020217-12:00:00
+#[get("/since?<query..>")]
+fn read_since(
+    query: SinceQuery,
+    tx_log: &State<TransactionLogState>,
+    settings: &State<SettingsState>,
+) -> Result<String, RouteError> {
+    let since = settings.parse_time(&query.ts)?;
+    // timestamps are not ordered like ids, so check every transaction
+    let all = tx_log.read().get_all()?;
+    Ok(itertools::join(
+        all.iter()
+            .filter(|t| *t.ts() > since)
             .map(|t| t.to_string()),
-    )
+        "\n",
+    ))
+}
+
+#[derive(Debug, FromForm)]
+struct AfterQuery {
+    hash: String,
+}
+
+// example: $ curl http://localhost:8000/transactions/after?hash=267C4D50...
+#[get("/after?<query..>")]
+fn read_after(
+    query: AfterQuery,
+    tx_log: &State<TransactionLogState>,
+) -> Result<String, RouteError> {
+    let hash: TransactionHash = query
+        .hash
+        .parse()
+        .map_err(|e| RouteError::Invalid("bad_hash", e))?;
+    match tx_log.read().after_hash(&hash)? {
+        Some(after) => Ok(itertools::join(
+            after.iter().map(|t| t.to_string()),
+            "\n",
+        )),
+        // the replica holds a transaction this log never had
+        None => Err(ApiError::new(
+            http::Status::Conflict,
+            "diverged",
+            format!("No transaction has hash {}, resync the log", hash),
+        ).into()),
+    }
+}
+
+// example: $ curl http://localhost:8000/transactions/by-hash/267C4D50...
+#[get("/by-hash/<hash>", rank = 2)]
+fn read_by_hash(
+    hash: String,
+    tx_log: &State<TransactionLogState>,
+) -> Result<Option<String>, RouteError> {
+    let hash: TransactionHash = hash.parse()
+        .map_err(|e| RouteError::Invalid("bad_hash", e))?;
+    Ok(tx_log.read().get_by_hash(&hash)?.map(|t| t.to_string()))
+}
+
+// example: $ curl -I http://localhost:8000/transactions/1
+#[head("/<id>")]
+fn transaction_exists(
+    id: u32,
+    tx_log: &State<TransactionLogState>,
+) -> Result<Option<()>, RouteError> {
+    Ok(if tx_log.read().contains(id)? { Some(()) } else { None })
+}
+
+/// The transaction together with the hash of its predecessor, which is all
+/// a client needs to recompute and check its hash.
+#[get("/<id>/proof")]
+fn read_transaction_proof(
+    id: u32,
+    tx_log: &State<TransactionLogState>,
+) -> Result<Option<Json<TransactionProof>>, RouteError> {
+    let log = tx_log.read();
+    let tx = match log.get_by_id(id)? {
+        Some(tx) => tx,
+        None => return Ok(None),
+    };
+    let prev_hash = if id > TransactionId::MIN_ID {
+        log.get_by_id(id - 1)?.map(|p| p.hash().to_string())
+    } else {
+        None
+    };
+    Ok(Some(Json(TransactionProof {
+        transaction: tx.to_string(),
+        prev_hash,
+    })))
+}
+
+#[derive(Debug, FromForm)]
+struct StreamQuery {
+    from: u32,
+}
+
+/// Streams the transactions of `log` from `from` on. The caller holds the
+/// read lock, so no transaction is missed or repeated.
+fn event_stream<P: AsRef<Path>>(
+    log: &DualLog<P>,
+    broadcast: &Broadcast,
+    from: Option<u32>,
+) -> Result<(http::ContentType, ByteStream![Vec<u8>]), RouteError> {
+    let rx = broadcast.subscribe();
+    let backlog = match from {
+        Some(from) => log.get_all()?
+            .into_iter()
+            .filter(|t| t.id().inner() >= from)
+            .collect(),
+        None => Vec::new(),
+    };
+    Ok((
+        http::ContentType::new("text", "event-stream"),
+        event_bytes(EventStream::new(backlog, rx)),
+    ))
+}
+
+// example: $ curl -N http://localhost:8000/transactions/stream?from=1
+#[get("/stream")]
+fn stream_transactions(
+    tx_log: &State<TransactionLogState>,
+    broadcast: &State<BroadcastState>,
+) -> Result<(http::ContentType, ByteStream![Vec<u8>]), RouteError> {
+    event_stream(&tx_log.read(), &broadcast.0, None)
+}
+
+#[get("/stream?<query..>")]
+fn stream_transactions_from(
+    query: StreamQuery,
+    tx_log: &State<TransactionLogState>,
+    broadcast: &State<BroadcastState>,
+) -> Result<(http::ContentType, ByteStream![Vec<u8>]), RouteError> {
+    event_stream(&tx_log.read(), &broadcast.0, Some(query.from))
 }
 
 // example: $ curl -X PUT -d '020217-12:00:00;05;06;hello world' \
 // http://localhost:8000/transactions/ -v
-#[put("/", data = "<input>")]
-fn write_transaction(
-    input: String,
-    tx_log: State<TransactionLogState>,
-    settings: State<SettingsState>,
-) -> Result<status::Created<String>, status::Custom<String>> {
+#[put("/", data = "<data>", rank = 2)]
+async fn write_transaction(
+    data: Data<'_>,
+    tx_log: &State<TransactionLogState>,
+    settings: &State<SettingsState>,
+) -> Result<status::Created<String>, RouteError> {
+    let input = read_body(data, settings.max_body_size).await?;
+    let (time, data) = parse_transaction_body(&input, settings)?;
+    let tx = tx_log.write().create(data, Some(time))?;
+
+    Ok(status::Created::new(
+        settings.url(&format!("/transactions/{}", tx.id().inner())),
+    ).body(tx.to_string()))
+}
+
+/// Guard of writes with `?dry_run=true`, forwarding all others.
+#[derive(Debug)]
+struct DryRun;
+
+#[rocket::async_trait]
+impl<'r> FromRequest<'r> for DryRun {
+    type Error = ();
+    async fn from_request(req: &'r Request<'_>) -> request::Outcome<Self, ()> {
+        let dry_run = req.query_fields().any(|field| {
+            field.name == "dry_run" && field.value == "true"
+        });
+        if dry_run {
+            Outcome::Success(DryRun)
+        } else {
+            Outcome::Forward(http::Status::NotFound)
+        }
+    }
+}
+
+/// Checks a write like `write_transaction` and answers 200 with the line it
+/// would create right now, without storing it or using up its id.
+// example: $ curl -X PUT -d '020217-12:00:00;05;06;hello world' \
+//     http://localhost:8000/transactions/?dry_run=true
+#[put("/", data = "<data>", rank = 1)]
+async fn write_transaction_dry_run(
+    _dry_run: DryRun,
+    data: Data<'_>,
+    tx_log: &State<TransactionLogState>,
+    settings: &State<SettingsState>,
+) -> Result<String, RouteError> {
+    let input = read_body(data, settings.max_body_size).await?;
+    let (time, data) = parse_transaction_body(&input, settings)?;
+    Ok(tx_log.read().preview(data, Some(time))?.to_string())
+}
+
+/// Splits a `<timestamp>;<gid>;<pid>;<text>` body.
+fn parse_transaction_body(
+    input: &str,
+    settings: &SettingsState,
+) -> Result<(TransactionTime, TransactionData), RouteError> {
     let mut parts = input.split(";");
 
-    let time: TransactionTime = parts
-        .next()
-        .ok_or(status::Custom(
-            http::Status::BadRequest,
-            "No timestamp given".into(),
-        ))?
-        .parse()
-        .map_err(|e| {
-            status::Custom(http::Status::BadRequest, format!("{:?}", e))
-        })?;
+    let time = settings.parse_time(parts.next().ok_or(
+        ApiError::bad_request("bad_timestamp", "No timestamp given"),
+    )?)?;
+
+    let data = TransactionData::parse_with_policy(
+        &itertools::join(parts, ";"),
+        settings.policy,
+    ).map_err(|e| RouteError::Invalid("bad_data", e))?;
+
+    Ok((time, data))
+}
+
+/// The log `name`, `None` if it doesn't exist and `create` isn't set.
+fn named_log(
+    logs: &LogManager,
+    name: &str,
+    create: bool,
+) -> Result<Option<SharedLog>, RouteError> {
+    if !LogManager::valid_name(name) {
+        return Err(ApiError::bad_request(
+            "bad_log_name",
+            format!("Invalid log name `{}`", name),
+        ).into());
+    }
+    if create {
+        Ok(Some(logs.get_or_create(name)?))
+    } else {
+        Ok(logs.get(name)?)
+    }
+}
+
+// the named routes are ranked below the default log's routes, e.g.
+// `/transactions/<id>` wins over `/<log_name>/transactions`
+#[get("/<log_name>/transactions", rank = 2)]
+fn read_all_named(
+    log_name: String,
+    logs: &State<LogManager>,
+) -> Result<Option<String>, RouteError> {
+    match named_log(logs, &log_name, false)? {
+        Some(log) => Ok(Some(itertools::join(
+            read_lock(&log).get_all()?.iter().map(|t| t.to_string()),
+            "\n",
+        ))),
+        None => Ok(None),
+    }
+}
+
+#[get("/<log_name>/transactions/last", rank = 2)]
+fn read_last_named(
+    log_name: String,
+    logs: &State<LogManager>,
+) -> Result<Option<String>, RouteError> {
+    match named_log(logs, &log_name, false)? {
+        Some(log) => {
+            Ok(read_lock(&log).last()?.map(|t| t.to_string()))
+        }
+        None => Ok(None),
+    }
+}
+
+#[get("/<log_name>/transactions/<id>", rank = 3)]
+fn read_named(
+    log_name: String,
+    id: u32,
+    logs: &State<LogManager>,
+) -> Result<Option<String>, RouteError> {
+    match named_log(logs, &log_name, false)? {
+        Some(log) => {
+            Ok(read_lock(&log).get_by_id(id)?.map(|t| t.to_string()))
+        }
+        None => Ok(None),
+    }
+}
 
-    let data: TransactionData = itertools::join(parts, ";").parse().map_err(
-        |e| status::Custom(http::Status::BadRequest, format!("{:?}", e)),
-    )?;
-
-    let tx = tx_log
-        .0
-        .lock()
-        .map_err(|_| {
-            status::Custom(http::Status::InternalServerError, "".into())
-        })?
-        .create(data, Some(time))
-        .map_err(|_| {
-            status::Custom(http::Status::InternalServerError, "".into())
+// example: $ curl -N http://localhost:8000/tenant-a/transactions/stream
+#[get("/<log_name>/transactions/stream?<from>", rank = 2)]
+fn stream_named(
+    log_name: String,
+    from: Option<u32>,
+    logs: &State<LogManager>,
+) -> Result<(http::ContentType, ByteStream![Vec<u8>]), RouteError> {
+    let log = named_log(logs, &log_name, false)?.ok_or_else(|| {
+        ApiError::new(
+            http::Status::NotFound,
+            "not_found",
+            format!("No log `{}`", log_name),
+        )
+    })?;
+    let log = read_lock(&log);
+    let broadcast = log.broadcast()
+        .expect("named logs publish their transactions");
+    event_stream(&log, broadcast, from)
+}
+
+// example: $ curl -X PUT -d '020217-12:00:00;05;06;hello world' \
+// http://localhost:8000/tenant-a/transactions -v
+#[put("/<log_name>/transactions", data = "<data>", rank = 2)]
+async fn write_named(
+    log_name: String,
+    data: Data<'_>,
+    logs: &State<LogManager>,
+    settings: &State<SettingsState>,
+) -> Result<status::Created<String>, RouteError> {
+    let input = read_body(data, settings.max_body_size).await?;
+    let (time, data) = parse_transaction_body(&input, settings)?;
+    let log = named_log(logs, &log_name, true)?.expect("log was created");
+    let tx = write_lock(&log).create(data, Some(time))?;
+
+    Ok(status::Created::new(settings.url(&format!(
+        "/{}/transactions/{}",
+        log_name,
+        tx.id().inner()
+    ))).body(tx.to_string()))
+}
+
+#[derive(Debug, Deserialize)]
+struct TransactionItem {
+    gid: u8,
+    pid: u8,
+    text: String,
+    ts: Option<String>,
+}
+
+impl TransactionItem {
+    /// Validates the item, on failure returning the error `code` with the
+    /// error. A missing `ts` is left to the log to fill in.
+    fn parse(
+        self,
+        settings: &SettingsState,
+    ) -> Result<
+        (TransactionData, Option<TransactionTime>),
+        (&'static str, transaction::Error),
+    > {
+        let time = match self.ts {
+            Some(ts) => Some(
+                TransactionTime::parse_with_format(&ts, settings.time_format)
+                    .map_err(|e| ("bad_timestamp", e))?,
+            ),
+            None => None,
+        };
+        let data = TransactionData::with_policy(
+            self.gid,
+            self.pid,
+            self.text,
+            settings.format,
+            settings.policy,
+        ).map_err(|e| ("bad_data", e))?;
+        Ok((data, time))
+    }
+}
+
+// example: $ curl -X PUT -H 'Content-Type: application/json' \
+// -d '{"gid":5,"pid":6,"text":"hello world","ts":"020217-12:00:00"}' \
+// http://localhost:8000/transactions/ -v
+#[put("/", format = "application/json", data = "<data>")]
+async fn write_transaction_json(
+    data: Data<'_>,
+    tx_log: &State<TransactionLogState>,
+    settings: &State<SettingsState>,
+) -> Result<status::Created<String>, RouteError> {
+    let input = read_body(data, settings.max_body_size).await?;
+    let (data, time) = parse_json::<TransactionItem>(&input)?
+        .parse(settings)
+        .map_err(|(code, e)| RouteError::Invalid(code, e))?;
+    let tx = tx_log.write().create(data, time)?;
+
+    Ok(status::Created::new(
+        settings.url(&format!("/transactions/{}", tx.id().inner())),
+    ).body(tx.to_string()))
+}
+
+// example: $ curl -X PUT -H 'Content-Type: application/json' \
+// -d '[{"gid":5,"pid":6,"text":"hello"},{"gid":5,"pid":6,"text":"world"}]' \
+// http://localhost:8000/transactions/batch -v
+#[put("/batch", format = "application/json", data = "<data>")]
+async fn write_transaction_batch(
+    data: Data<'_>,
+    tx_log: &State<TransactionLogState>,
+    settings: &State<SettingsState>,
+) -> Result<status::Custom<Json<Vec<u32>>>, RouteError> {
+    let input = read_body(data, settings.max_body_size).await?;
+    let items = parse_json::<Vec<TransactionItem>>(&input)?;
+    // validate everything up front, so an invalid item rejects the whole batch
+    let mut batch = Vec::with_capacity(items.len());
+    for (i, item) in items.into_iter().enumerate() {
+        let item = item.parse(settings).map_err(|(_, e)| {
+            let msg = format!("Item {}: {:?}", i, e);
+            ApiError::bad_request("bad_data", msg)
         })?;
+        batch.push(item);
+    }
 
-    Ok(status::Created(
-        format!("{}/transactions/{}", settings.base_url, tx.id().inner()),
-        Some(tx.to_string()),
-    ))
+    let ids = tx_log
+        .write()
+        .create_batch(batch)?
+        .iter()
+        .map(|tx| tx.id().inner())
+        .collect();
+    Ok(status::Custom(http::Status::Created, Json(ids)))
 }
 
+/// Reads the request body, rejecting it once it exceeds `limit` bytes
+/// instead of buffering it completely.
+async fn read_body(
+    data: Data<'_>,
+    limit: u64,
+) -> Result<String, RouteError> {
+    let input = data.open((limit + 1).bytes())
+        .into_string()
+        .await
+        .map_err(|e| ApiError::bad_request("bad_body", format!("{:?}", e)))?;
+    if input.len() as u64 > limit {
+        return Err(ApiError::new(
+            http::Status::PayloadTooLarge,
+            "payload_too_large",
+            format!("Body exceeds {} bytes", limit),
+        ).into());
+    }
+    Ok(input.into_inner())
+}
 
-fn main() {
-    let settings = SettingsState::default();
-    println!("Settings:\n{:#?}", &settings);
-    {
-        let _ = OpenOptions::new()
-            .write(true)
-            .create(true)
-            .open(settings.clone().tx_log_file)
-            .unwrap();
+/// Parses the JSON body `input` read with `read_body`, so it is limited like
+/// the other bodies.
+fn parse_json<T: DeserializeOwned>(input: &str) -> Result<T, RouteError> {
+    serde_json::from_str(input).map_err(|e| {
+        ApiError::bad_request("bad_body", format!("{}", e)).into()
+    })
+}
+
+#[derive(Debug, FromForm)]
+struct PruneQuery {
+    before: String,
+}
+
+/// Drops the transactions older than `before` from the start of the log,
+/// see `SimpleFileLog::prune_older_than`. The remaining hashes change, so
+/// this is only served with `--enable-admin`.
+// example: $ curl -X POST localhost:8000/admin/prune?before=010118-00:00:00
+#[post("/admin/prune?<query..>")]
+fn prune(
+    query: PruneQuery,
+    tx_log: &State<TransactionLogState>,
+    settings: &State<SettingsState>,
+) -> Result<content::RawJson<String>, RouteError> {
+    if !settings.enable_admin {
+        return Err(ApiError::new(
+            http::Status::NotFound,
+            "not_found",
+            "Admin routes are disabled",
+        ).into());
+    }
+    let cutoff = settings.parse_time(&query.before)?;
+    let pruned = tx_log.write().prune_older_than(&cutoff)?;
+    Ok(content::RawJson(json!({ "pruned": pruned }).to_string()))
+}
+
+#[get("/healthz")]
+fn healthz() -> content::RawJson<&'static str> {
+    content::RawJson(r#"{"status":"ok"}"#)
+}
+
+#[get("/readyz")]
+fn readyz(
+    tx_log: &State<TransactionLogState>,
+    chain: &State<ChainStatusState>,
+) -> Result<content::RawJson<&'static str>, ApiError> {
+    let unavailable = |reason: String| {
+        ApiError::new(http::Status::ServiceUnavailable, "not_ready", reason)
+    };
+    tx_log
+        .read()
+        .check_file()
+        .map_err(|e| unavailable(format!("{:?}", e)))?;
+    if let Some(failure) = chain.0.failure() {
+        return Err(ApiError::new(
+            http::Status::ServiceUnavailable,
+            "chain_broken",
+            format!("chain_ok=false, {}", failure),
+        ));
     }
-    let log = DualLog::load(settings.clone().tx_log_file).unwrap();
+    Ok(content::RawJson(r#"{"status":"ready"}"#))
+}
 
-    rocket::ignite()
-        .manage(TransactionLogState(Mutex::new(log)))
+// example: $ curl http://localhost:8000/openapi.json
+#[get("/openapi.json")]
+fn openapi_document() -> content::RawJson<String> {
+    content::RawJson(openapi::document().to_string())
+}
+
+// compares the file against the in-memory log, which reads the whole file
+#[get("/consistency")]
+fn consistency(
+    tx_log: &State<TransactionLogState>,
+) -> Result<content::RawJson<&'static str>, ApiError> {
+    tx_log.read().check_consistency().map_err(|report| {
+        ApiError::new(
+            http::Status::Conflict,
+            "inconsistent",
+            format!("{:?}", report),
+        )
+    })?;
+    Ok(content::RawJson(r#"{"status":"consistent"}"#))
+}
+
+/// Methods and request headers browsers may use across origins.
+const CORS_METHODS: &'static str = "GET, HEAD, PUT, POST, OPTIONS";
+const CORS_HEADERS: &'static str = "Content-Type";
+
+/// Answers CORS preflight requests, the headers are added by the fairing
+/// attached with `cors_origin`.
+#[options("/<_path..>")]
+fn preflight(_path: PathBuf) -> status::NoContent {
+    status::NoContent
+}
+
+/// Fairing allowing `origin` to read every response.
+fn cors(origin: String) -> AdHoc {
+    AdHoc::on_response("CORS", move |_, res| {
+        let origin = origin.clone();
+        Box::pin(async move {
+            res.set_raw_header("Access-Control-Allow-Origin", origin);
+            res.set_raw_header("Access-Control-Allow-Methods", CORS_METHODS);
+            res.set_raw_header("Access-Control-Allow-Headers", CORS_HEADERS);
+        })
+    })
+}
+
+#[catch(404)]
+fn not_found(req: &Request) -> ApiError {
+    ApiError::new(
+        http::Status::NotFound,
+        "not_found",
+        format!("Nothing found at {}", req.uri()),
+    )
+}
+
+#[catch(500)]
+fn internal_error() -> ApiError {
+    ApiError::new(
+        http::Status::InternalServerError,
+        "internal_error",
+        "Internal server error",
+    )
+}
+
+fn rocket(
+    settings: SettingsState,
+    log: DualLog<String>,
+) -> rocket::Rocket<rocket::Build> {
+    let broadcast = Arc::new(Broadcast::new());
+    let log = log.with_broadcast(broadcast.clone());
+    let mut format = format!("v{}", settings.format.version());
+    if settings.policy == CharPolicy::Escaped {
+        format.push_str("+escaped");
+    }
+    let mut logs = LogManager::new(settings.log_dir.clone())
+        .with_reject_duplicates(settings.reject_duplicates)
+        .with_format(settings.log_format());
+    if let Some(max) = settings.max_transactions {
+        logs = logs.with_max_transactions(max);
+    }
+    if let Some(max) = settings.max_log_bytes {
+        logs = logs.with_max_bytes(max);
+    }
+    let chain = Arc::new(ChainStatus::new());
+    if let Some(secs) = settings.verify_interval {
+        chain_verifier::spawn(
+            settings.tx_log_file.clone(),
+            Duration::from_secs(secs),
+            chain.clone(),
+        );
+    }
+    let rocket = match listen_config(&settings) {
+        Ok(config) => rocket::custom(config),
+        Err(e) => panic!("{}", e),
+    };
+    let rocket = match settings.cors_origin.clone() {
+        Some(origin) => {
+            rocket.attach(cors(origin)).mount("/", routes![preflight])
+        }
+        None => rocket,
+    };
+    rocket
+        .attach(AdHoc::on_response("Transaction format", move |req, res| {
+            let format = format.clone();
+            Box::pin(async move {
+                // the default log and the named logs
+                let mut segments = req.uri().path().segments();
+                if segments.any(|segment| segment == "transactions") {
+                    res.set_raw_header("X-Transaction-Format", format);
+                }
+            })
+        }))
+        .manage(TransactionLogState::new(log, settings.cache_size))
+        .manage(BroadcastState(broadcast))
+        .manage(ChainStatusState(chain))
+        .manage(logs)
         .manage(settings)
+        .mount(
+            "/",
+            routes![healthz, readyz, consistency, openapi_document, prune],
+        )
+        .mount(
+            "/",
+            routes![
+                read_all_named,
+                read_last_named,
+                read_named,
+                stream_named,
+                write_named,
+            ],
+        )
+        .register("/", catchers![not_found, internal_error])
         .mount(
             "/transactions",
             routes![
                 read_all_transactions,
                 read_last_transaction,
+                wait_last_transaction,
+                read_merkle_root,
+                export_transactions,
+                read_group_count,
+                read_tail,
+                read_tail_n,
+                read_since,
+                read_after,
+                read_by_hash,
                 read_transaction,
-                write_transaction
+                transaction_exists,
+                read_transaction_proof,
+                stream_transactions,
+                stream_transactions_from,
+                write_transaction,
+                write_transaction_dry_run,
+                write_transaction_json,
+                write_transaction_batch
             ],
         )
-        .launch();
+}
+
+/// Rewrites `log` with recomputed hashes into `output`, keeping a copy of
+/// the original when rebuilding in place.
+fn rebuild(log: &str, output: &str) -> Result<(), FileError> {
+    eprintln!(
+        "WARNING: rebuilding changes the hashes of every edited transaction \
+         and all following it. Proofs, replicas and any other references to \
+         the old hashes will no longer verify."
+    );
+    if log == output {
+        let backup = format!("{}.orig", log);
+        fs::copy(log, &backup)?;
+        println!("Original log saved as {}", backup);
+    }
+    let changed = SimpleFileLog::new(log).rebuild(output)?;
+    println!("Rebuilt {} into {}, {} hashes changed", log, output, changed);
+    Ok(())
+}
+
+fn main() {
+    let matches = App::new("transaction")
+        .arg(
+            Arg::with_name("name")
+                .long("name")
+                .value_name("NAME")
+                .help("Cluster identity seeding the genesis transaction")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("reject-duplicates")
+                .long("reject-duplicates")
+                .help("Reject transactions repeating the previous data"),
+        )
+        .arg(
+            Arg::with_name("enable-admin")
+                .long("enable-admin")
+                .help("Serve the /admin routes, e.g. pruning the log"),
+        )
+        .arg(
+            Arg::with_name("data-format")
+                .long("data-format")
+                .value_name("VERSION")
+                .help("Format version of data created from JSON (1 or 2)")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("escape-text")
+                .long("escape-text")
+                .help("Store any text created from JSON, escaping ; and \\n"),
+        )
+        .arg(
+            Arg::with_name("time-format")
+                .long("time-format")
+                .value_name("FORMAT")
+                .help("Time format of the logs: compact (default) or iso")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("log-dir")
+                .long("log-dir")
+                .value_name("DIR")
+                .help("Directory of the logs below /<log_name>/transactions")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("address")
+                .long("address")
+                .value_name("ADDRESS")
+                .help("IP address or host name to listen on")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("port")
+                .long("port")
+                .value_name("PORT")
+                .help("Port to listen on")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("env")
+                .long("env")
+                .value_name("ENV")
+                .help("Rocket environment: development, staging or production")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("tls-cert")
+                .long("tls-cert")
+                .value_name("FILE")
+                .help("PEM certificate chain to serve HTTPS with")
+                .requires("tls-key")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("tls-key")
+                .long("tls-key")
+                .value_name("FILE")
+                .help("PEM private key of the TLS certificate")
+                .requires("tls-cert")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("cors-origin")
+                .long("cors-origin")
+                .value_name("ORIGIN")
+                .help("Origin allowed to call the API from browsers, or *")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("workers")
+                .long("workers")
+                .value_name("COUNT")
+                .help("Threads serving requests, writes still run one by one")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("max-transactions")
+                .long("max-transactions")
+                .value_name("COUNT")
+                .help("Reject writes once the log holds COUNT transactions")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("max-log-bytes")
+                .long("max-log-bytes")
+                .value_name("BYTES")
+                .help("Reject writes growing the log file beyond BYTES")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("cache-size")
+                .long("cache-size")
+                .value_name("COUNT")
+                .help("Cache the COUNT transactions last read by id")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("verify-interval")
+                .long("verify-interval")
+                .value_name("SECONDS")
+                .help("Verify the whole chain in the background this often")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("recover")
+                .long("recover")
+                .help("Drop corrupt lines of the log instead of failing"),
+        )
+        .arg(
+            Arg::with_name("stdin")
+                .long("stdin")
+                .help("Append <gid>;<pid>;<text> lines from stdin, no server"),
+        )
+        .arg(
+            Arg::with_name("fail-fast")
+                .long("fail-fast")
+                .help("Stop at the first malformed line of --stdin")
+                .requires("stdin"),
+        )
+        .subcommand(
+            SubCommand::with_name("rebuild")
+                .about("Recompute all hashes of a log, e.g. after editing it")
+                .arg(
+                    Arg::with_name("log")
+                        .value_name("LOG")
+                        .help("Log to rebuild, the served log by default"),
+                )
+                .arg(
+                    Arg::with_name("output")
+                        .long("output")
+                        .value_name("FILE")
+                        .help("Write the rebuilt log here instead of in place")
+                        .takes_value(true),
+                ),
+        )
+        .get_matches();
+
+    let mut settings = SettingsState::default();
+    if let Some(matches) = matches.subcommand_matches("rebuild") {
+        let log = matches
+            .value_of("log")
+            .map(|l| l.to_owned())
+            .unwrap_or_else(|| settings.tx_log_file.clone());
+        let output = matches.value_of("output").unwrap_or(&log).to_owned();
+        process::exit(match rebuild(&log, &output) {
+            Ok(()) => 0,
+            Err(e) => {
+                eprintln!("Rebuild failed: {:?}", e);
+                1
+            }
+        });
+    }
+    settings.name = matches.value_of("name").map(|n| n.to_owned());
+    settings.reject_duplicates = matches.is_present("reject-duplicates");
+    settings.enable_admin = matches.is_present("enable-admin");
+    if let Some(dir) = matches.value_of("log-dir") {
+        settings.log_dir = dir.to_owned();
+    }
+    settings.address = matches.value_of("address").map(|a| a.to_owned());
+    if let Some(port) = matches.value_of("port") {
+        settings.port = match port.parse() {
+            Ok(port) => Some(port),
+            Err(_) => {
+                eprintln!("Invalid port `{}`", port);
+                process::exit(1);
+            }
+        };
+    }
+    if let Some(workers) = matches.value_of("workers") {
+        settings.workers = match workers.parse() {
+            Ok(workers) if workers > 0 => Some(workers),
+            _ => {
+                eprintln!("Invalid worker count `{}`", workers);
+                process::exit(1);
+            }
+        };
+    }
+    if let Some(max) = matches.value_of("max-transactions") {
+        settings.max_transactions = match max.parse() {
+            Ok(max) => Some(max),
+            Err(_) => {
+                eprintln!("Invalid transaction limit `{}`", max);
+                process::exit(1);
+            }
+        };
+    }
+    if let Some(max) = matches.value_of("max-log-bytes") {
+        settings.max_log_bytes = match max.parse() {
+            Ok(max) => Some(max),
+            Err(_) => {
+                eprintln!("Invalid byte limit `{}`", max);
+                process::exit(1);
+            }
+        };
+    }
+    if let Some(size) = matches.value_of("cache-size") {
+        settings.cache_size = match size.parse() {
+            Ok(size) => size,
+            Err(_) => {
+                eprintln!("Invalid cache size `{}`", size);
+                process::exit(1);
+            }
+        };
+    }
+    if let Some(env) = matches.value_of("env") {
+        settings.env = match env.parse() {
+            Ok(env) => Some(env),
+            Err(_) => {
+                eprintln!("Unknown environment `{}`", env);
+                process::exit(1);
+            }
+        };
+    }
+    settings.tls_cert = matches.value_of("tls-cert").map(|c| c.to_owned());
+    settings.tls_key = matches.value_of("tls-key").map(|k| k.to_owned());
+    settings.cors_origin =
+        matches.value_of("cors-origin").map(|o| o.to_owned());
+    if let Some(secs) = matches.value_of("verify-interval") {
+        settings.verify_interval = match secs.parse() {
+            Ok(secs) if secs > 0 => Some(secs),
+            _ => {
+                eprintln!("Invalid verify interval `{}`", secs);
+                process::exit(1);
+            }
+        };
+    }
+    if let Err(e) = listen_config(&settings) {
+        eprintln!("Refusing to start: {}", e);
+        process::exit(1);
+    }
+    if settings.address.is_some() || settings.port.is_some()
+        || settings.tls_cert.is_some()
+    {
+        let scheme = if settings.tls_cert.is_some() {
+            "https"
+        } else {
+            "http"
+        };
+        settings.base_url = format!(
+            "{}://{}:{}",
+            scheme,
+            settings.address.clone().unwrap_or("localhost".into()),
+            settings.port.unwrap_or(8000)
+        );
+    }
+    if let Some(version) = matches.value_of("data-format") {
+        let format = version.parse().map(DataFormat::from_version);
+        settings.format = match format {
+            Ok(Ok(format)) => format,
+            _ => {
+                eprintln!("Unknown data format version: {}", version);
+                process::exit(1);
+            }
+        };
+    }
+    if matches.is_present("escape-text") {
+        settings.policy = CharPolicy::Escaped;
+    }
+    if let Some(format) = matches.value_of("time-format") {
+        match format.parse::<TimeFormat>() {
+            Ok(format) => settings.time_format = format,
+            Err(e) => {
+                eprintln!("{:?}", e);
+                process::exit(1);
+            }
+        }
+    }
+    // stdout carries the created transactions in --stdin mode, so warnings
+    // and recovery reports go to stderr
+    let stdin = matches.is_present("stdin");
+    if !stdin {
+        println!("Settings:\n{:#?}", &settings);
+    }
+    {
+        let _ = OpenOptions::new()
+            .write(true)
+            .create(true)
+            .open(settings.clone().tx_log_file)
+            .unwrap();
+    }
+    let file_log = SimpleFileLog::new(settings.clone().tx_log_file)
+        .with_format(settings.log_format());
+    let log = if matches.is_present("recover") {
+        DualLog::load_recovering_from(file_log).map(|(log, dropped)| {
+            eprintln!("Recovered log, dropped {} lines", dropped);
+            log
+        })
+    } else {
+        DualLog::load_from(file_log)
+    };
+    let log = match log {
+        Ok(log) => log,
+        Err(e) => {
+            // a log without a header, but written in another format, fails
+            // to parse
+            eprintln!(
+                "Refusing to start: cannot load {} in the format `{}`: {:?}",
+                settings.tx_log_file,
+                settings.log_format(),
+                e
+            );
+            process::exit(1);
+        }
+    };
+    match log.format() {
+        Ok(format) if format == settings.log_format() => {}
+        Ok(format) => {
+            eprintln!(
+                "Refusing to start: {} is written in the format `{}`, not `{}`",
+                settings.tx_log_file,
+                format,
+                settings.log_format()
+            );
+            process::exit(1);
+        }
+        Err(e) => {
+            eprintln!("Refusing to start: {:?}", e);
+            process::exit(1);
+        }
+    }
+    let mut log = log.with_reject_duplicates(settings.reject_duplicates);
+    if let Some(max) = settings.max_transactions {
+        log = log.with_max_transactions(max);
+    }
+    if let Some(max) = settings.max_log_bytes {
+        log = log.with_max_bytes(max);
+    }
+    if let Some(ref name) = settings.name {
+        if let Err(e) = log.ensure_genesis(name) {
+            eprintln!("Refusing to start: {:?}", e);
+            process::exit(1);
+        }
+    }
+
+    if stdin {
+        let input = io::stdin();
+        let output = io::stdout();
+        let fail_fast = matches.is_present("fail-fast");
+        let piped = pipe::pipe(
+            &mut log,
+            input.lock(),
+            output.lock(),
+            io::stderr(),
+            fail_fast,
+        );
+        process::exit(match piped {
+            Ok(_) => 0,
+            Err(e) => {
+                eprintln!("Pipe failed: {:?}", e);
+                1
+            }
+        });
+    }
+
+    if let Err(e) = rocket::execute(rocket(settings, log).launch()) {
+        eprintln!("Launch failed: {}", e);
+        process::exit(1);
+    }
+}
+
+
+#[cfg(test)]
+mod test {
+
+    use super::*;
+    use std::fs;
+    use std::io::Write;
+    use std::net::TcpStream;
+    use std::panic;
+    use std::sync::mpsc as std_mpsc;
+    use std::thread;
+    use std::time::Instant;
+    use rocket::config::LogLevel;
+    use rocket::local::blocking::{Client, LocalResponse};
+
+    fn assert_send_sync<T: Send + Sync + 'static>() {}
+
+    /// Everything passed to `manage` is shared between worker threads.
+    #[test]
+    fn managed_state_is_send_sync() {
+        assert_send_sync::<TransactionLogState>();
+        assert_send_sync::<BroadcastState>();
+        assert_send_sync::<ChainStatusState>();
+        assert_send_sync::<LogManager>();
+        assert_send_sync::<SettingsState>();
+    }
+
+    fn client(name: &str) -> (Client, String) {
+        let path = env::temp_dir()
+            .join(format!("tx_main_test_{}_{}.txt", name, process::id()))
+            .to_string_lossy()
+            .into_owned();
+        fs::File::create(&path).unwrap();
+        let settings = SettingsState {
+            tx_log_file: path.clone(),
+            log_dir: format!("{}.logs", path),
+            ..SettingsState::default()
+        };
+        let log = DualLog::load(path.clone()).unwrap();
+        let client = Client::tracked(rocket(settings, log)).unwrap();
+        (client, path)
+    }
+
+    #[test]
+    fn write_transaction_body_limit() {
+        let (client, path) = client("body_limit");
+        let response = client
+            .put("/transactions/")
+            .body("020217-12:00:00;05;06;hello world")
+            .dispatch();
+        assert_eq!(response.status(), http::Status::Created);
+
+        let text = "x".repeat(64 * 1024);
+        let response = client
+            .put("/transactions/")
+            .body(format!("020217-12:00:00;05;06;{}", text))
+            .dispatch();
+        assert_eq!(response.status(), http::Status::PayloadTooLarge);
+
+        let item = format!(r#"{{"gid":5,"pid":6,"text":"{}"}}"#, text);
+        for (uri, body) in vec![
+            ("/transactions/", item.clone()),
+            ("/transactions/batch", format!("[{}]", item)),
+        ] {
+            let response = client
+                .put(uri)
+                .header(http::ContentType::JSON)
+                .body(body)
+                .dispatch();
+            assert_eq!(response.status(), http::Status::PayloadTooLarge);
+        }
+        fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn write_transaction_dry_run() {
+        let (client, path) = client("dry_run");
+        let body = "020217-12:00:00;05;06;hello world";
+        let response = client
+            .put("/transactions/?dry_run=true")
+            .body(body)
+            .dispatch();
+        assert_eq!(response.status(), http::Status::Ok);
+        let preview = response.into_string().unwrap();
+        assert!(preview.starts_with("00000001;"));
+        assert_eq!(DualLog::load(path.clone()).unwrap().len(), 0);
+
+        let response = client
+            .put("/transactions/?dry_run=false")
+            .body(body)
+            .dispatch();
+        assert_eq!(response.status(), http::Status::Created);
+        assert_eq!(response.into_string(), Some(preview));
+
+        let response = client
+            .put("/transactions/?dry_run=true")
+            .body("020217-12:00:00;05")
+            .dispatch();
+        assert_eq!(response.status(), http::Status::BadRequest);
+        assert_eq!(error_json(response)["code"], "bad_data");
+        assert_eq!(DualLog::load(path.clone()).unwrap().len(), 1);
+        fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn write_transaction_json_matches_text() {
+        let (text_client, text_path) = client("put_text");
+        let response = text_client
+            .put("/transactions/")
+            .body("020217-12:00:00;05;06;hello world")
+            .dispatch();
+        assert_eq!(response.status(), http::Status::Created);
+        let text_line = response.into_string().unwrap();
+
+        let (json_client, json_path) = client("put_json");
+        let put = |body: &str| {
+            json_client
+                .put("/transactions/")
+                .header(http::ContentType::JSON)
+                .body(body.to_owned())
+                .dispatch()
+        };
+        let response = put(
+            r#"{"gid":5,"pid":6,"text":"hello world","ts":"020217-12:00:00"}"#,
+        );
+        assert_eq!(response.status(), http::Status::Created);
+        assert_eq!(response.into_string().unwrap(), text_line);
+        let stored = |path: &str| {
+            DualLog::load(path.to_owned()).unwrap().get_all().unwrap()[0]
+                .to_string()
+        };
+        assert_eq!(stored(&json_path), stored(&text_path));
+
+        let response = put(r#"{"gid":5,"pid":6,"text":"now"}"#);
+        assert_eq!(response.status(), http::Status::Created);
+        let response = put(r#"{"gid":5,"pid":6"#);
+        assert_eq!(response.status(), http::Status::BadRequest);
+        assert_eq!(error_json(response)["code"], "bad_body");
+        let response = put(r#"{"gid":5,"pid":6,"text":"x","ts":"never"}"#);
+        assert_eq!(response.status(), http::Status::BadRequest);
+        assert_eq!(error_json(response)["code"], "bad_timestamp");
+        fs::remove_file(&text_path).unwrap();
+        fs::remove_file(&json_path).unwrap();
+    }
+
+    #[test]
+    fn write_transaction_batch_all_or_nothing() {
+        let (client, path) = client("batch");
+        let response = client
+            .put("/transactions/batch")
+            .header(http::ContentType::JSON)
+            .body(
+                r#"[{"gid":5,"pid":6,"text":"hello"},
+                    {"gid":5,"pid":6,"text":"world","ts":"020217-12:00:00"}]"#,
+            )
+            .dispatch();
+        assert_eq!(response.status(), http::Status::Created);
+
+        let response = client
+            .put("/transactions/batch")
+            .header(http::ContentType::JSON)
+            .body(
+                r#"[{"gid":5,"pid":6,"text":"valid"},
+                    {"gid":100,"pid":6,"text":"invalid"}]"#,
+            )
+            .dispatch();
+        assert_eq!(response.status(), http::Status::BadRequest);
+
+        let log = DualLog::load(path.clone()).unwrap();
+        let all = log.get_all().unwrap();
+        assert_eq!(all.len(), 2);
+        assert_eq!(all[1].data().text(), "world");
+        fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn read_tail_of_log() {
+        let (client, path) = client("tail");
+        let response = client.get("/transactions/tail").dispatch();
+        assert_eq!(response.status(), http::Status::Ok);
+        assert_eq!(response.into_string(), Some(String::new()));
+
+        for i in 0..3 {
+            client
+                .put("/transactions/")
+                .body(format!("020217-12:00:00;05;06;tx {}", i))
+                .dispatch();
+        }
+        let response = client.get("/transactions/tail?n=1").dispatch();
+        let body = response.into_string().unwrap();
+        assert!(body.starts_with("00000003;"));
+        assert_eq!(body.lines().count(), 1);
+
+        let response = client.get("/transactions/tail?n=50").dispatch();
+        let body = response.into_string().unwrap();
+        assert_eq!(body.lines().count(), 3);
+        assert!(body.starts_with("00000001;"));
+        fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn format_header() {
+        let (client, path) = client("format_header");
+        let response = client.get("/transactions/").dispatch();
+        let format = |r: &LocalResponse| {
+            r.headers().get_one("X-Transaction-Format").map(|f| f.to_owned())
+        };
+        assert_eq!(format(&response), Some("v1".to_owned()));
+        let response = client.get("/healthz").dispatch();
+        assert_eq!(format(&response), None);
+        let response = client
+            .put("/tenant-a/transactions")
+            .body("020217-12:00:00;05;06;named")
+            .dispatch();
+        assert_eq!(response.status(), http::Status::Created);
+        assert_eq!(format(&response), Some("v1".to_owned()));
+        let response = client.get("/tenant-a/transactions/1").dispatch();
+        assert_eq!(format(&response), Some("v1".to_owned()));
+
+        let settings = SettingsState {
+            tx_log_file: path.clone(),
+            format: DataFormat::V2,
+            ..SettingsState::default()
+        };
+        let log = DualLog::load(path.clone()).unwrap();
+        let client = Client::tracked(rocket(settings, log)).unwrap();
+        let response = client
+            .put("/transactions/batch")
+            .header(http::ContentType::JSON)
+            .body(r#"[{"gid":150,"pid":6,"text":"wide"}]"#)
+            .dispatch();
+        assert_eq!(response.status(), http::Status::Created);
+        assert_eq!(format(&response), Some("v2".to_owned()));
+        let response = client.get("/transactions/last").dispatch();
+        assert!(response.into_string().unwrap().contains(";150;006;wide;"));
+        fs::remove_file(&path).unwrap();
+        fs::remove_dir_all(format!("{}.logs", path)).unwrap();
+    }
+
+    #[test]
+    fn write_escaped_text() {
+        let (plain, path) = client("escaped_text");
+        let body = r#"{"gid":5,"pid":6,"text":"semi;colon\nnewline"}"#;
+        let response = plain
+            .put("/transactions/")
+            .header(http::ContentType::JSON)
+            .body(body)
+            .dispatch();
+        assert_eq!(response.status(), http::Status::BadRequest);
+
+        let settings = SettingsState {
+            tx_log_file: path.clone(),
+            policy: CharPolicy::Escaped,
+            ..SettingsState::default()
+        };
+        let file_log =
+            SimpleFileLog::new(path.clone()).with_format(settings.log_format());
+        let log = DualLog::load_from(file_log).unwrap();
+        let client = Client::tracked(rocket(settings, log)).unwrap();
+        let response = client
+            .put("/transactions/")
+            .header(http::ContentType::JSON)
+            .body(body)
+            .dispatch();
+        assert_eq!(response.status(), http::Status::Created);
+        assert_eq!(
+            response.headers().get_one("X-Transaction-Format"),
+            Some("v1+escaped")
+        );
+        let line = response.into_string().unwrap();
+        assert_eq!(line.lines().count(), 1);
+        // the text body is escaped like a line of the log
+        let response = client
+            .put("/transactions/")
+            .body("020217-12:00:00;05;06;tab\\there")
+            .dispatch();
+        assert_eq!(response.status(), http::Status::Created);
+
+        // the log records its policy and unescapes the texts on reload
+        let reloaded = DualLog::load(path.clone()).unwrap();
+        let texts: Vec<String> = reloaded
+            .get_all()
+            .unwrap()
+            .iter()
+            .map(|t| t.data().text().to_owned())
+            .collect();
+        assert_eq!(texts, vec!["semi;colon\nnewline", "tab\there"]);
+        fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn write_to_full_log() {
+        let (_, path) = client("full");
+        let settings = SettingsState {
+            tx_log_file: path.clone(),
+            ..SettingsState::default()
+        };
+        let log = DualLog::load(path.clone()).unwrap().with_max_transactions(1);
+        let client = Client::tracked(rocket(settings, log)).unwrap();
+        let put = || {
+            client
+                .put("/transactions/")
+                .body("020217-12:00:00;05;06;hello world")
+                .dispatch()
+        };
+        assert_eq!(put().status(), http::Status::Created);
+        let response = put();
+        assert_eq!(response.status(), http::Status::InsufficientStorage);
+        assert!(response.into_string().unwrap().contains("log_full"));
+        fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn write_to_full_named_log() {
+        let (_, path) = client("full_named");
+        let settings = SettingsState {
+            tx_log_file: path.clone(),
+            log_dir: format!("{}.logs", path),
+            max_transactions: Some(1),
+            ..SettingsState::default()
+        };
+        let log = DualLog::load(path.clone()).unwrap();
+        let client = Client::tracked(rocket(settings, log)).unwrap();
+        let put = || {
+            client
+                .put("/a/transactions")
+                .body("020217-12:00:00;05;06;hello world")
+                .dispatch()
+        };
+        assert_eq!(put().status(), http::Status::Created);
+        assert_eq!(put().status(), http::Status::InsufficientStorage);
+        fs::remove_dir_all(format!("{}.logs", path)).unwrap();
+        fs::remove_file(&path).unwrap();
+    }
+
+    fn allowed_origin(response: &LocalResponse) -> Option<String> {
+        response
+            .headers()
+            .get_one("Access-Control-Allow-Origin")
+            .map(|h| h.to_owned())
+    }
+
+    #[test]
+    fn cors_preflight() {
+        let (plain, path) = client("no_cors");
+        let response = plain.options("/transactions/").dispatch();
+        assert_eq!(response.status(), http::Status::NotFound);
+        let response = plain.get("/healthz").dispatch();
+        assert_eq!(allowed_origin(&response), None);
+        fs::remove_file(&path).unwrap();
+
+        let (_, path) = client("cors");
+        let settings = SettingsState {
+            tx_log_file: path.clone(),
+            cors_origin: Some("https://dashboard.example".to_owned()),
+            ..SettingsState::default()
+        };
+        let log = DualLog::load(path.clone()).unwrap();
+        let client = Client::tracked(rocket(settings, log)).unwrap();
+        let response = client
+            .options("/transactions/")
+            .header(http::Header::new("Origin", "https://dashboard.example"))
+            .header(http::Header::new("Access-Control-Request-Method", "PUT"))
+            .dispatch();
+        assert_eq!(response.status(), http::Status::NoContent);
+        let origin = Some("https://dashboard.example".to_owned());
+        assert_eq!(allowed_origin(&response), origin);
+        let headers = response.headers();
+        let methods = headers.get_one("Access-Control-Allow-Methods");
+        assert!(methods.unwrap().contains("PUT"));
+        assert_eq!(
+            headers.get_one("Access-Control-Allow-Headers"),
+            Some("Content-Type")
+        );
+        let response = client.get("/transactions/").dispatch();
+        assert_eq!(response.status(), http::Status::Ok);
+        assert_eq!(allowed_origin(&response), origin);
+        fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn head_transaction() {
+        let (client, path) = client("head");
+        client
+            .put("/transactions/")
+            .body("020217-12:00:00;05;06;hello world")
+            .dispatch();
+        let response = client.head("/transactions/1").dispatch();
+        assert_eq!(response.status(), http::Status::Ok);
+        assert_eq!(response.into_string(), None);
+        let response = client.head("/transactions/2").dispatch();
+        assert_eq!(response.status(), http::Status::NotFound);
+        fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn named_logs() {
+        let (client, path) = client("named");
+        let put = |log: &str, text: &str| {
+            client
+                .put(format!("/{}/transactions", log))
+                .body(format!("020217-12:00:00;05;06;{}", text))
+                .dispatch()
+        };
+        let response = client.get("/a/transactions/last").dispatch();
+        assert_eq!(response.status(), http::Status::NotFound);
+
+        let response = put("a", "first a");
+        assert_eq!(response.status(), http::Status::Created);
+        assert_eq!(
+            response.headers().get_one("Location"),
+            Some("http://localhost/a/transactions/1")
+        );
+        put("a", "second a");
+        put("b", "first b");
+
+        let response = client.get("/a/transactions/last").dispatch();
+        assert!(response.into_string().unwrap().starts_with("00000002;"));
+        let response = client.get("/b/transactions").dispatch();
+        let body = response.into_string().unwrap();
+        assert_eq!(body.lines().count(), 1);
+        assert!(body.contains("first b"));
+        let response = client.get("/b/transactions/1").dispatch();
+        assert!(response.into_string().unwrap().contains("first b"));
+        // the default log is untouched
+        let response = client.get("/transactions/").dispatch();
+        assert_eq!(response.into_string(), Some(String::new()));
+
+        let response = put("bad.name", "rejected");
+        assert_eq!(response.status(), http::Status::BadRequest);
+
+        // a deleted file starts a new log like the default log
+        fs::remove_file(format!("{}.logs/a.txt", path)).unwrap();
+        let response = client.get("/a/transactions").dispatch();
+        assert_eq!(response.status(), http::Status::NotFound);
+        let response = client.get("/a/transactions/stream").dispatch();
+        assert_eq!(response.status(), http::Status::NotFound);
+        let response = put("a", "new a");
+        assert!(response.into_string().unwrap().starts_with("00000001;"));
+        fs::remove_dir_all(format!("{}.logs", path)).unwrap();
+        fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn read_since_timestamp() {
+        let (client, path) = client("since");
+        for ts in &["020217-12:00:00", "020217-13:00:00", "020217-11:00:00"] {
+            client
+                .put("/transactions/")
+                .body(format!("{};05;06;at {}", ts, ts))
+                .dispatch();
+        }
+        let response = client
+            .get("/transactions/since?ts=020217-12:00:00")
+            .dispatch();
+        assert_eq!(response.status(), http::Status::Ok);
+        let body = response.into_string().unwrap();
+        assert_eq!(body.lines().count(), 1);
+        assert!(body.contains("at 020217-13:00:00"));
+
+        let response = client
+            .get("/transactions/since?ts=010217-00:00:00")
+            .dispatch();
+        assert_eq!(response.into_string().unwrap().lines().count(), 3);
+
+        let response =
+            client.get("/transactions/since?ts=yesterday").dispatch();
+        assert_eq!(response.status(), http::Status::BadRequest);
+        assert_eq!(error_json(response)["code"], "bad_timestamp");
+        fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn read_after_hash() {
+        let (client, path) = client("after");
+        let mut hashes = Vec::new();
+        for i in 0..3 {
+            let response = client
+                .put("/transactions/")
+                .body(format!("020217-12:00:00;05;06;tx {}", i))
+                .dispatch();
+            let line = response.into_string().unwrap();
+            hashes.push(line.rsplit(';').next().unwrap().to_owned());
+        }
+        let after = |hash: &str| {
+            client
+                .get(format!("/transactions/after?hash={}", hash))
+                .dispatch()
+        };
+
+        let response = after(&hashes[0]);
+        assert_eq!(response.status(), http::Status::Ok);
+        let body = response.into_string().unwrap();
+        let ids: Vec<_> = body.lines().map(|l| &l[..8]).collect();
+        assert_eq!(ids, vec!["00000002", "00000003"]);
+
+        let response = after(&hashes[2]);
+        assert_eq!(response.status(), http::Status::Ok);
+        assert_eq!(response.into_string(), Some(String::new()));
+
+        let response = after(&"0".repeat(64));
+        assert_eq!(response.status(), http::Status::Conflict);
+        assert_eq!(error_json(response)["code"], "diverged");
+
+        let response = after("nothex");
+        assert_eq!(response.status(), http::Status::BadRequest);
+        assert_eq!(error_json(response)["code"], "bad_hash");
+        fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn read_transaction_by_hash() {
+        let (client, path) = client("by_hash");
+        let mut lines = Vec::new();
+        for i in 0..2 {
+            let response = client
+                .put("/transactions/")
+                .body(format!("020217-12:00:00;05;06;tx {}", i))
+                .dispatch();
+            lines.push(response.into_string().unwrap());
+        }
+        let by_hash = |hash: &str| {
+            client
+                .get(format!("/transactions/by-hash/{}", hash))
+                .dispatch()
+        };
+
+        let hash = lines[1].rsplit(';').next().unwrap();
+        let response = by_hash(hash);
+        assert_eq!(response.status(), http::Status::Ok);
+        assert_eq!(response.into_string(), Some(lines[1].clone()));
+
+        let response = by_hash(&"0".repeat(64));
+        assert_eq!(response.status(), http::Status::NotFound);
+
+        let response = by_hash("nothex");
+        assert_eq!(response.status(), http::Status::BadRequest);
+        assert_eq!(error_json(response)["code"], "bad_hash");
+        fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn wait_for_last() {
+        let (client, path) = client("wait_last");
+        let wait = |after: u32| {
+            let response = client
+                .get(format!("/transactions/last?wait=100&after={}", after))
+                .dispatch();
+            (response.status(), response.into_string())
+        };
+        assert_eq!(wait(0).0, http::Status::NoContent);
+        assert_eq!(wait(1).0, http::Status::NotFound);
+
+        let response = client
+            .put("/transactions/")
+            .body("020217-12:00:00;05;06;awaited")
+            .dispatch();
+        let line = response.into_string();
+        // answered right away once the log moved past `after`
+        assert_eq!(wait(0), (http::Status::Ok, line));
+        assert_eq!(wait(1).0, http::Status::NoContent);
+        fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn prune_old_transactions() {
+        let (client, path) = client("prune");
+        for day in &["01", "02", "03"] {
+            client
+                .put("/transactions/")
+                .body(format!("{}1017-10:00:00;05;06;day {}", day, day))
+                .dispatch();
+        }
+        let response = client
+            .post("/admin/prune?before=021017-12:00:00")
+            .dispatch();
+        assert_eq!(response.status(), http::Status::NotFound);
+        assert_eq!(SimpleFileLog::new(&path).get_all().unwrap().len(), 3);
+
+        let settings = SettingsState {
+            tx_log_file: path.clone(),
+            enable_admin: true,
+            ..SettingsState::default()
+        };
+        let log = DualLog::load(path.clone()).unwrap();
+        let client = Client::tracked(rocket(settings, log)).unwrap();
+        let prune = |before: &str| {
+            client
+                .post(format!("/admin/prune?before={}", before))
+                .dispatch()
+        };
+        let response = prune("021017-12:00:00");
+        assert_eq!(response.status(), http::Status::Ok);
+        let body: serde_json::Value =
+            serde_json::from_str(&response.into_string().unwrap()).unwrap();
+        assert_eq!(body["pruned"], 2);
+        let response = client.get("/transactions/1").dispatch();
+        assert_eq!(response.status(), http::Status::NotFound);
+        assert!(SimpleFileLog::new(&path).get_all().is_ok());
+
+        let response = prune("041017-00:00:00");
+        assert_eq!(response.status(), http::Status::Conflict);
+        assert_eq!(error_json(response)["code"], "dangling_chain");
+        let response = prune("yesterday");
+        assert_eq!(response.status(), http::Status::BadRequest);
+        assert_eq!(error_json(response)["code"], "bad_timestamp");
+        fs::remove_file(format!("{}.pruned", path)).unwrap();
+        fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn cached_reads_follow_prune() {
+        let (_, path) = client("cached");
+        let settings = SettingsState {
+            tx_log_file: path.clone(),
+            cache_size: 4,
+            enable_admin: true,
+            ..SettingsState::default()
+        };
+        let log = DualLog::load(path.clone()).unwrap();
+        let client = Client::tracked(rocket(settings, log)).unwrap();
+        for day in &["01", "02", "03"] {
+            client
+                .put("/transactions/")
+                .body(format!("{}1017-10:00:00;05;06;day {}", day, day))
+                .dispatch();
+        }
+        let read = |id: u32| {
+            client
+                .get(format!("/transactions/{}", id))
+                .dispatch()
+                .into_string()
+                .unwrap()
+        };
+        let before = read(3);
+        assert_eq!(read(3), before);
+        client.post("/admin/prune?before=021017-00:00:00").dispatch();
+        let after = read(3);
+        assert_ne!(after, before);
+        assert_eq!(after, DualLog::load(path.clone()).unwrap()
+            .get_by_id(3).unwrap().unwrap().to_string());
+
+        // a deleted file empties the cache too
+        fs::remove_file(format!("{}.pruned", path)).unwrap();
+        fs::remove_file(&path).unwrap();
+        let response = client.get("/transactions/3").dispatch();
+        assert_eq!(response.status(), http::Status::NotFound);
+    }
+
+    #[test]
+    fn stream_released_on_disconnect() {
+        let (client, path) = client("stream_released");
+        let broadcast = &client.rocket().state::<BroadcastState>().unwrap().0;
+        let response = client.get("/transactions/stream").dispatch();
+        assert_eq!(response.status(), http::Status::Ok);
+        assert_eq!(broadcast.subscriber_count(), 1);
+        drop(response);
+        assert_eq!(broadcast.subscriber_count(), 0);
+
+        // a named log which never gets another write releases it too
+        client
+            .put("/a/transactions")
+            .body("020217-12:00:00;05;06;streamed")
+            .dispatch();
+        let logs = client.rocket().state::<LogManager>().unwrap();
+        let log = logs.get("a").unwrap().unwrap();
+        let named = read_lock(&log).broadcast().unwrap().clone();
+        let response = client.get("/a/transactions/stream?from=1").dispatch();
+        assert_eq!(response.status(), http::Status::Ok);
+        assert_eq!(named.subscriber_count(), 1);
+        drop(response);
+        assert_eq!(named.subscriber_count(), 0);
+        fs::remove_dir_all(format!("{}.logs", path)).unwrap();
+        fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn read_root() {
+        let (client, path) = client("root");
+        let root = || {
+            let response = client.get("/transactions/root").dispatch();
+            assert_eq!(response.status(), http::Status::Ok);
+            response.into_string().unwrap()
+        };
+        let empty = root();
+        assert_eq!(empty, merkle_root(Vec::new()).to_string());
+        client
+            .put("/transactions/")
+            .body("020217-12:00:00;05;06;rooted")
+            .dispatch();
+        let one = root();
+        assert_ne!(one, empty);
+        assert_eq!(one, root());
+        fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn export_log_file() {
+        let (client, path) = client("export");
+        for text in &["first", "second"] {
+            client
+                .put("/transactions/")
+                .body(format!("020217-12:00:00;05;06;{}", text))
+                .dispatch();
+        }
+        let response = client.get("/transactions/export").dispatch();
+        assert_eq!(response.status(), http::Status::Ok);
+        assert_eq!(
+            response.headers().get_one("Content-Disposition"),
+            Some(r#"attachment; filename="tx_log.txt""#)
+        );
+        let mut on_disk = Vec::new();
+        File::open(&path).unwrap().read_to_end(&mut on_disk).unwrap();
+        assert_eq!(on_disk.iter().filter(|&&b| b == b'\n').count(), 2);
+        assert_eq!(response.into_bytes().unwrap(), on_disk);
+        fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn read_group_counts() {
+        let (client, path) = client("group_count");
+        for gid in &[3, 5, 3] {
+            client
+                .put("/transactions/")
+                .body(format!("020217-12:00:00;{:02};06;grouped", gid))
+                .dispatch();
+        }
+        let count = |gid: &str| {
+            let response = client
+                .get(format!("/transactions/group/{}/count", gid))
+                .dispatch();
+            (response.status(), response.into_string().unwrap())
+        };
+        assert_eq!(count("3"), (http::Status::Ok, "2".to_owned()));
+        assert_eq!(count("05"), (http::Status::Ok, "1".to_owned()));
+        assert_eq!(count("7"), (http::Status::Ok, "0".to_owned()));
+        let total: usize = (0..100)
+            .map(|gid| count(&gid.to_string()))
+            .filter(|&(status, _)| status == http::Status::Ok)
+            .map(|(_, n)| n.parse::<usize>().unwrap())
+            .sum();
+        assert_eq!(total, 3);
+        assert_eq!(count("x").0, http::Status::BadRequest);
+        fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn openapi_lists_routes() {
+        let (client, path) = client("openapi");
+        let response = client.get("/openapi.json").dispatch();
+        assert_eq!(response.status(), http::Status::Ok);
+        assert_eq!(response.content_type(), Some(http::ContentType::JSON));
+        let doc: serde_json::Value =
+            serde_json::from_str(&response.into_string().unwrap()).unwrap();
+        let transactions = &doc["paths"]["/transactions/"];
+        assert!(transactions["get"].is_object());
+        assert!(transactions["put"].is_object());
+        assert!(doc["paths"]["/transactions/{id}"]["head"].is_object());
+        assert!(doc["components"]["schemas"]["ApiError"].is_object());
+        fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn listen_address() {
+        let mut settings = SettingsState::default();
+        settings.env = Some("dev".parse().unwrap());
+        let config = Config::from(listen_config(&settings).unwrap());
+        assert_eq!(config.address, IpAddr::from([127, 0, 0, 1]));
+
+        for address in &["0.0.0.0", "127.0.0.1", "::1", "localhost", "a.b-c"] {
+            assert!(valid_address(address), "{}", address);
+        }
+        settings.address = Some("127.0.0.1".into());
+        settings.port = Some(9000);
+        let config = Config::from(listen_config(&settings).unwrap());
+        assert_eq!(config.address, IpAddr::from([127, 0, 0, 1]));
+        assert_eq!(config.port, 9000);
+
+        for address in &["", "not an address", "-bad.host", "a..b"] {
+            settings.address = Some(address.to_string());
+            let err = listen_config(&settings).unwrap_err();
+            assert!(err.starts_with("Invalid address"), "{}", err);
+        }
+    }
+
+    #[test]
+    fn listen_environment() {
+        let mut settings = SettingsState::default();
+        settings.env = Some("production".parse().unwrap());
+        let config = Config::from(listen_config(&settings).unwrap());
+        assert_eq!(config.profile, Config::RELEASE_PROFILE);
+        assert_eq!(config.log_level, LogLevel::Critical);
+        assert_eq!(config.port, 8000);
+        // without --address, the address of Rocket.toml is kept
+        assert_eq!(config.address, IpAddr::from([0, 0, 0, 0]));
+
+        settings.env = Some("dev".parse().unwrap());
+        let config = Config::from(listen_config(&settings).unwrap());
+        assert_eq!(config.profile, Config::DEBUG_PROFILE);
+        assert!("nowhere".parse::<Environment>().is_err());
+    }
+
+    #[test]
+    fn listen_flags_keep_rocket_toml() {
+        let mut settings = SettingsState::default();
+        settings.env = Some("staging".parse().unwrap());
+        settings.workers = Some(3);
+        let config = Config::from(listen_config(&settings).unwrap());
+        assert_eq!(config.workers, 3);
+        assert_eq!(config.address, IpAddr::from([0, 0, 0, 0]));
+        assert_eq!(config.port, 8000);
+
+        settings.port = Some(9000);
+        let config = Config::from(listen_config(&settings).unwrap());
+        assert_eq!(config.address, IpAddr::from([0, 0, 0, 0]));
+        assert_eq!(config.port, 9000);
+    }
+
+    #[test]
+    fn missing_tls_files() {
+        let (_, path) = client("tls");
+        let mut settings = SettingsState::default();
+        settings.tls_cert = Some(format!("{}.missing.pem", path));
+        settings.tls_key = Some(path.clone());
+        let err = listen_config(&settings).unwrap_err();
+        assert!(err.starts_with("Cannot read TLS certificate"), "{}", err);
+
+        settings.tls_cert = Some(path.clone());
+        settings.tls_key = Some(env::temp_dir().to_string_lossy().into());
+        let err = listen_config(&settings).unwrap_err();
+        assert!(err.starts_with("Cannot read TLS key"), "{}", err);
+
+        settings.tls_key = None;
+        assert!(listen_config(&settings).is_err());
+        fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn url_joins_base_url() {
+        for base_url in &["http://host", "http://host/", "http://host//"] {
+            let settings = SettingsState {
+                base_url: base_url.to_string(),
+                ..SettingsState::default()
+            };
+            assert_eq!(
+                settings.url("/transactions/1"),
+                "http://host/transactions/1"
+            );
+            assert_eq!(
+                settings.url("transactions/1"),
+                "http://host/transactions/1"
+            );
+        }
+    }
+
+    fn error_json(response: LocalResponse) -> serde_json::Value {
+        serde_json::from_str(&response.into_string().unwrap()).unwrap()
+    }
+
+    #[test]
+    fn write_transaction_errors_are_json() {
+        let (client, path) = client("error_json");
+        let response = client
+            .put("/transactions/")
+            .body("not a time;05;06;hello world")
+            .dispatch();
+        assert_eq!(response.status(), http::Status::BadRequest);
+        assert_eq!(response.content_type(), Some(http::ContentType::JSON));
+        let body = error_json(response);
+        assert_eq!(body["status"], 400);
+        assert_eq!(body["code"], "bad_timestamp");
+        assert!(body["message"].is_string());
+
+        let bad_hash = "0".repeat(64);
+        fs::OpenOptions::new()
+            .append(true)
+            .open(&path)
+            .unwrap()
+            .write_all(
+                format!("00000001;041017-10:00:00;00;01;Test;{}\n", bad_hash)
+                    .as_bytes(),
+            )
+            .unwrap();
+        let response = client
+            .put("/transactions/")
+            .body("020217-12:00:00;05;06;hello world")
+            .dispatch();
+        assert_eq!(response.status(), http::Status::Conflict);
+        let body = error_json(response);
+        assert_eq!(body["status"], 409);
+        assert_eq!(body["code"], "verify_failed");
+        fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn transaction_proof() {
+        let (client, path) = client("proof");
+        for text in &["first", "second"] {
+            client
+                .put("/transactions/")
+                .body(format!("020217-12:00:00;05;06;{}", text))
+                .dispatch();
+        }
+
+        for &(id, has_prev) in &[(1, false), (2, true)] {
+            let response =
+                client.get(format!("/transactions/{}/proof", id)).dispatch();
+            assert_eq!(response.status(), http::Status::Ok);
+            let proof: TransactionProof =
+                serde_json::from_str(&response.into_string().unwrap())
+                    .unwrap();
+            assert_eq!(proof.prev_hash.is_some(), has_prev);
+            let tx: transaction::Transaction =
+                proof.transaction.parse().unwrap();
+            let prev_hash: Option<transaction::TransactionHash> =
+                proof.prev_hash.map(|h| h.parse().unwrap());
+            assert_eq!(
+                transaction::verify_with_prev_hash(&tx, prev_hash.as_ref()),
+                Ok(())
+            );
+        }
+
+        let response = client.get("/transactions/3/proof").dispatch();
+        assert_eq!(response.status(), http::Status::NotFound);
+        fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn not_found_is_json() {
+        let (client, path) = client("not_found");
+        for uri in &["/unknown/path", "/transactions/42"] {
+            let response = client.get(*uri).dispatch();
+            assert_eq!(response.status(), http::Status::NotFound);
+            assert_eq!(response.content_type(), Some(http::ContentType::JSON));
+            let body = error_json(response);
+            assert_eq!(body["code"], "not_found");
+        }
+        fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn recover_poisoned_lock() {
+        let path = env::temp_dir()
+            .join(format!("tx_main_test_poisoned_{}.txt", process::id()));
+        fs::File::create(&path).unwrap();
+        let state = TransactionLogState::new(
+            DualLog::load(path.to_string_lossy().into_owned()).unwrap(),
+            0,
+        );
+        let _ = panic::catch_unwind(panic::AssertUnwindSafe(|| {
+            let _guard = state.0.write().unwrap();
+            panic!("poison the lock");
+        }));
+        assert!(state.0.is_poisoned());
+
+        let data = TransactionData::new(1, 2, "after poisoning").unwrap();
+        state.write().create(data, None).unwrap();
+        assert_eq!(state.read().get_all().unwrap().len(), 1);
+        fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn reads_share_the_log() {
+        let path = env::temp_dir()
+            .join(format!("tx_main_test_rwlock_{}.txt", process::id()));
+        fs::File::create(&path).unwrap();
+        let state = Arc::new(TransactionLogState::new(
+            DualLog::load(path.to_string_lossy().into_owned()).unwrap(),
+            0,
+        ));
+        let hold = Duration::from_millis(300);
+
+        // two readers holding the log at once take one hold, not two
+        let start = Instant::now();
+        let readers = (0..2)
+            .map(|_| {
+                let state = state.clone();
+                thread::spawn(move || {
+                    let log = state.read();
+                    thread::sleep(hold);
+                    log.get_all().unwrap().len()
+                })
+            })
+            .collect::<Vec<_>>();
+        for reader in readers {
+            assert_eq!(reader.join().unwrap(), 0);
+        }
+        assert!(start.elapsed() < hold * 2);
+
+        // a writer waits for the reader and excludes further readers
+        let log = state.read();
+        assert!(state.0.try_write().is_err());
+        let writer = {
+            let state = state.clone();
+            thread::spawn(move || {
+                let mut log = state.write();
+                let data = TransactionData::new(1, 2, "exclusive").unwrap();
+                log.create(data, None).unwrap();
+                thread::sleep(hold);
+            })
+        };
+        thread::sleep(hold);
+        drop(log);
+        thread::sleep(hold / 3);
+        assert!(state.0.try_read().is_err());
+        writer.join().unwrap();
+        assert_eq!(state.read().get_all().unwrap().len(), 1);
+        fs::remove_file(&path).unwrap();
+    }
+
+    /// Launches the server on a free port and asks it for `/healthz`.
+    #[test]
+    fn server_boots() {
+        let (_, path) = client("boots");
+        let settings = SettingsState {
+            tx_log_file: path.clone(),
+            address: Some("127.0.0.1".into()),
+            port: Some(0),
+            ..SettingsState::default()
+        };
+        let log = DualLog::load(path.clone()).unwrap();
+        let (launched, rx) = std_mpsc::channel();
+        let server = rocket(settings, log).attach(AdHoc::on_liftoff(
+            "Launched",
+            move |rocket| {
+                let port = rocket.config().port;
+                let _ = launched.send((port, rocket.shutdown()));
+                Box::pin(async {})
+            },
+        ));
+        let server = thread::spawn(move || {
+            rocket::execute(server.launch()).map_err(|e| e.to_string())
+        });
+
+        let (port, shutdown) =
+            rx.recv_timeout(Duration::from_secs(10)).unwrap();
+        let mut stream = TcpStream::connect(("127.0.0.1", port)).unwrap();
+        stream
+            .write_all(b"GET /healthz HTTP/1.1\r\nHost: localhost\r\n\
+                         Connection: close\r\n\r\n")
+            .unwrap();
+        let mut response = String::new();
+        stream.read_to_string(&mut response).unwrap();
+        assert!(response.starts_with("HTTP/1.1 200 OK"), "{}", response);
+        assert!(response.ends_with(r#"{"status":"ok"}"#), "{}", response);
+        shutdown.notify();
+        assert!(server.join().unwrap().is_ok());
+        fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn healthz_and_readyz() {
+        let (client, path) = client("readyz");
+        let response = client.get("/healthz").dispatch();
+        assert_eq!(response.status(), http::Status::Ok);
+        let response = client.get("/readyz").dispatch();
+        assert_eq!(response.status(), http::Status::Ok);
+        let response = client.get("/consistency").dispatch();
+        assert_eq!(response.status(), http::Status::Ok);
+        fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn readyz_unreadable_log() {
+        let (client, path) = client("readyz_unreadable");
+        fs::OpenOptions::new()
+            .append(true)
+            .open(&path)
+            .unwrap()
+            .write_all(b"garbage\n")
+            .unwrap();
+        let response = client.get("/readyz").dispatch();
+        assert_eq!(response.status(), http::Status::ServiceUnavailable);
+        let response = client.get("/healthz").dispatch();
+        assert_eq!(response.status(), http::Status::Ok);
+        fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn deleted_log_file() {
+        let (client, path) = client("deleted");
+        let write = |text: &str| {
+            client
+                .put("/transactions/")
+                .body(format!("020217-12:00:00;05;06;{}", text))
+                .dispatch()
+        };
+        assert_eq!(write("before").status(), http::Status::Created);
+        fs::remove_file(&path).unwrap();
+
+        let response = client.get("/transactions/").dispatch();
+        assert_eq!(response.status(), http::Status::Ok);
+        assert_eq!(response.into_string(), Some(String::new()));
+        let response = client.get("/transactions/last").dispatch();
+        assert_eq!(response.status(), http::Status::NotFound);
+
+        let response = write("after");
+        assert_eq!(response.status(), http::Status::Created);
+        assert!(response.into_string().unwrap().starts_with("00000001;"));
+        assert_eq!(DualLog::load(path.clone()).unwrap().len(), 1);
+        fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn readyz_broken_chain() {
+        let (client, path) = client("readyz_chain");
+        for i in 0..3 {
+            client
+                .put("/transactions/")
+                .body(format!("020217-12:00:00;05;06;tx {}", i))
+                .dispatch();
+        }
+        assert_eq!(client.get("/readyz").dispatch().status(), http::Status::Ok);
+
+        // the last line still parses and verifies, only the chain is broken
+        let log = DualLog::load(path.clone()).unwrap();
+        let mut contents = String::new();
+        fs::File::open(&path)
+            .unwrap()
+            .read_to_string(&mut contents)
+            .unwrap();
+        fs::File::create(&path)
+            .unwrap()
+            .write_all(contents.replace("tx 0", "tx X").as_bytes())
+            .unwrap();
+        let settings = SettingsState {
+            tx_log_file: path.clone(),
+            verify_interval: Some(3600),
+            ..SettingsState::default()
+        };
+        let client = Client::tracked(rocket(settings, log)).unwrap();
+        // the first verification runs right after the start
+        let mut response = client.get("/readyz").dispatch();
+        for _ in 0..100 {
+            if response.status() != http::Status::Ok {
+                break;
+            }
+            thread::sleep(Duration::from_millis(50));
+            response = client.get("/readyz").dispatch();
+        }
+        assert_eq!(response.status(), http::Status::ServiceUnavailable);
+        let error = error_json(response);
+        assert_eq!(error["code"], "chain_broken");
+        assert!(error["message"].as_str().unwrap().contains("transaction 1"));
+        fs::remove_file(&path).unwrap();
+    }
 }
@@ -0,0 +1,428 @@
+use serde_json::Value;
+
+/// Text response with one transaction per line.
+fn lines(description: &str) -> Value {
+    json!({
+        "description": description,
+        "content": { "text/plain": { "schema": { "type": "string" } } }
+    })
+}
+
+fn error(description: &str) -> Value {
+    json!({
+        "description": description,
+        "content": {
+            "application/json": {
+                "schema": { "$ref": "#/components/schemas/ApiError" }
+            }
+        }
+    })
+}
+
+fn query(name: &str, schema: Value, description: &str) -> Value {
+    json!({
+        "name": name,
+        "in": "query",
+        "required": true,
+        "schema": schema,
+        "description": description
+    })
+}
+
+fn path(name: &str, schema: Value) -> Value {
+    json!({ "name": name, "in": "path", "required": true, "schema": schema })
+}
+
+/// Hand written OpenAPI 3 description of the routes, kept next to them in
+/// `main.rs`. Update both together.
+pub fn document() -> Value {
+    let id = || path("id", json!({ "type": "integer", "minimum": 1 }));
+    let log_name = || {
+        path(
+            "log_name",
+            json!({ "type": "string", "pattern": "^[A-Za-z0-9_-]{1,64}$" }),
+        )
+    };
+    let created = || lines("The stored transaction, located by `Location`");
+    let body = || {
+        json!({
+            "required": true,
+            "content": {
+                "text/plain": {
+                    "schema": {
+                        "type": "string",
+                        "example": "020217-12:00:00;05;06;hello world"
+                    }
+                }
+            }
+        })
+    };
+    json!({
+        "openapi": "3.0.0",
+        "info": {
+            "title": "transaction",
+            "version": env!("CARGO_PKG_VERSION")
+        },
+        "paths": {
+            "/transactions/": {
+                "get": {
+                    "summary": "All transactions",
+                    "responses": { "200": lines("All transactions") }
+                },
+                "put": {
+                    "summary": "Create a transaction",
+                    "description": "Takes `<ts>;<gid>;<pid>;<text>` as text, \
+                        or a `TransactionItem` as JSON. Text with \
+                        `?dry_run=true` answers 200 with the line it would \
+                        create, without storing it.",
+                    "requestBody": {
+                        "required": true,
+                        "content": {
+                            "text/plain": body()["content"]["text/plain"],
+                            "application/json": {
+                                "schema": {
+                                    "$ref":
+                                        "#/components/schemas/TransactionItem"
+                                }
+                            }
+                        }
+                    },
+                    "responses": {
+                        "200": lines("The transaction of a dry run"),
+                        "201": created(),
+                        "400": error("Invalid timestamp or data"),
+                        "409": error("Duplicate or unverifiable transaction"),
+                        "413": error("Body too large"),
+                        "507": error("The log is full")
+                    }
+                }
+            },
+            "/transactions/batch": {
+                "put": {
+                    "summary": "Create several transactions, all or nothing",
+                    "requestBody": {
+                        "required": true,
+                        "content": {
+                            "application/json": {
+                                "schema": {
+                                    "type": "array",
+                                    "items": {
+                                        "$ref":
+                                        "#/components/schemas/TransactionItem"
+                                    }
+                                }
+                            }
+                        }
+                    },
+                    "responses": {
+                        "201": {
+                            "description": "Ids of the created transactions",
+                            "content": {
+                                "application/json": {
+                                    "schema": {
+                                        "type": "array",
+                                        "items": { "type": "integer" }
+                                    }
+                                }
+                            }
+                        },
+                        "400": error("An invalid item"),
+                        "507": error("The log is full")
+                    }
+                }
+            },
+            "/transactions/last": {
+                "get": {
+                    "summary": "The last transaction",
+                    "description": "With `wait` and `after`, waits up to \
+                        `wait` ms while `after` is still the last id.",
+                    "parameters": [{
+                        "name": "wait",
+                        "in": "query",
+                        "schema": { "type": "integer", "maximum": 30000 }
+                    }, {
+                        "name": "after",
+                        "in": "query",
+                        "schema": { "type": "integer" }
+                    }],
+                    "responses": {
+                        "200": lines("The last transaction"),
+                        "204": { "description": "Nothing new within `wait`" },
+                        "404": error("The log is empty")
+                    }
+                }
+            },
+            "/transactions/root": {
+                "get": {
+                    "summary": "Merkle root over all transaction hashes",
+                    "responses": { "200": lines("The root as uppercase hex") }
+                }
+            },
+            "/transactions/export": {
+                "get": {
+                    "summary": "The log file as stored, for backups",
+                    "responses": {
+                        "200": {
+                            "description": "The file as an attachment \
+                                named `tx_log.txt`",
+                            "content": {
+                                "text/plain": {
+                                    "schema": {
+                                        "type": "string",
+                                        "format": "binary"
+                                    }
+                                }
+                            }
+                        }
+                    }
+                }
+            },
+            "/transactions/group/{gid}/count": {
+                "get": {
+                    "summary": "Number of transactions of a group",
+                    "parameters": [path("gid", json!({ "type": "integer" }))],
+                    "responses": {
+                        "200": lines("The number"),
+                        "400": error("Invalid gid")
+                    }
+                }
+            },
+            "/transactions/tail": {
+                "get": {
+                    "summary": "The last transactions, oldest first",
+                    "parameters": [{
+                        "name": "n",
+                        "in": "query",
+                        "schema": {
+                            "type": "integer",
+                            "default": 10,
+                            "maximum": 1000
+                        }
+                    }],
+                    "responses": { "200": lines("The last `n` transactions") }
+                }
+            },
+            "/transactions/since": {
+                "get": {
+                    "summary": "Transactions later than a time",
+                    "parameters": [query(
+                        "ts",
+                        json!({ "type": "string" }),
+                        "Time as `ddmmyy-HH:MM:SS`",
+                    )],
+                    "responses": {
+                        "200": lines("Transactions after `ts`"),
+                        "400": error("Invalid timestamp")
+                    }
+                }
+            },
+            "/transactions/after": {
+                "get": {
+                    "summary": "Transactions following a hash, for replicas",
+                    "parameters": [query(
+                        "hash",
+                        json!({ "type": "string" }),
+                        "Uppercase hex hash of the last known transaction",
+                    )],
+                    "responses": {
+                        "200": lines("Transactions after `hash`"),
+                        "400": error("Invalid hash"),
+                        "409": error("Unknown hash, the replica diverged")
+                    }
+                }
+            },
+            "/transactions/by-hash/{hash}": {
+                "get": {
+                    "summary": "A transaction by hash",
+                    "parameters": [path("hash", json!({ "type": "string" }))],
+                    "responses": {
+                        "200": lines("The transaction"),
+                        "400": error("Invalid hash"),
+                        "404": error("No such transaction")
+                    }
+                }
+            },
+            "/transactions/stream": {
+                "get": {
+                    "summary": "Server-sent events of new transactions",
+                    "parameters": [{
+                        "name": "from",
+                        "in": "query",
+                        "schema": { "type": "integer" },
+                        "description": "Also replay transactions from this id"
+                    }],
+                    "responses": {
+                        "200": {
+                            "description": "One event per transaction",
+                            "content": {
+                                "text/event-stream": {
+                                    "schema": { "type": "string" }
+                                }
+                            }
+                        }
+                    }
+                }
+            },
+            "/transactions/{id}": {
+                "get": {
+                    "summary": "A transaction by id",
+                    "parameters": [id()],
+                    "responses": {
+                        "200": lines("The transaction"),
+                        "404": error("No such transaction")
+                    }
+                },
+                "head": {
+                    "summary": "Whether a transaction exists",
+                    "parameters": [id()],
+                    "responses": {
+                        "200": { "description": "It exists" },
+                        "404": { "description": "No such transaction" }
+                    }
+                }
+            },
+            "/transactions/{id}/proof": {
+                "get": {
+                    "summary": "A transaction with the hash it chains onto",
+                    "parameters": [id()],
+                    "responses": {
+                        "200": {
+                            "description": "The proof",
+                            "content": {
+                                "application/json": {
+                                    "schema": {
+                                        "$ref":
+                                        "#/components/schemas/TransactionProof"
+                                    }
+                                }
+                            }
+                        },
+                        "404": error("No such transaction")
+                    }
+                }
+            },
+            "/{log_name}/transactions": {
+                "get": {
+                    "summary": "All transactions of a named log",
+                    "parameters": [log_name()],
+                    "responses": {
+                        "200": lines("All transactions"),
+                        "400": error("Invalid log name"),
+                        "404": error("No such log")
+                    }
+                },
+                "put": {
+                    "summary": "Create a transaction, creating the log",
+                    "parameters": [log_name()],
+                    "requestBody": body(),
+                    "responses": {
+                        "201": created(),
+                        "400": error("Invalid log name, timestamp or data")
+                    }
+                }
+            },
+            "/{log_name}/transactions/last": {
+                "get": {
+                    "summary": "The last transaction of a named log",
+                    "parameters": [log_name()],
+                    "responses": {
+                        "200": lines("The last transaction"),
+                        "404": error("No such log, or it is empty")
+                    }
+                }
+            },
+            "/{log_name}/transactions/{id}": {
+                "get": {
+                    "summary": "A transaction of a named log by id",
+                    "parameters": [log_name(), id()],
+                    "responses": {
+                        "200": lines("The transaction"),
+                        "404": error("No such log or transaction")
+                    }
+                }
+            },
+            "/admin/prune": {
+                "post": {
+                    "summary": "Drop the oldest transactions",
+                    "description": "Re-anchors the remaining chain, which \
+                        changes all remaining hashes. Only served with \
+                        `--enable-admin`.",
+                    "parameters": [query(
+                        "before",
+                        json!({ "type": "string" }),
+                        "Time as `ddmmyy-HH:MM:SS`",
+                    )],
+                    "responses": {
+                        "200": { "description": "The number of pruned ones" },
+                        "400": error("Invalid timestamp"),
+                        "404": error("Admin routes are disabled"),
+                        "409": error("Would leave a gap or an empty log")
+                    }
+                }
+            },
+            "/healthz": {
+                "get": {
+                    "summary": "Whether the server runs",
+                    "responses": { "200": { "description": "It runs" } }
+                }
+            },
+            "/readyz": {
+                "get": {
+                    "summary": "Whether the log file is usable",
+                    "responses": {
+                        "200": { "description": "Ready" },
+                        "503": error("The log file is not usable")
+                    }
+                }
+            },
+            "/consistency": {
+                "get": {
+                    "summary": "Compare the file with the in-memory log",
+                    "responses": {
+                        "200": { "description": "Consistent" },
+                        "409": error("The differences")
+                    }
+                }
+            }
+        },
+        "components": {
+            "schemas": {
+                "ApiError": {
+                    "type": "object",
+                    "required": ["status", "code", "message"],
+                    "properties": {
+                        "status": { "type": "integer" },
+                        "code": { "type": "string" },
+                        "message": { "type": "string" }
+                    }
+                },
+                "TransactionItem": {
+                    "type": "object",
+                    "required": ["gid", "pid", "text"],
+                    "properties": {
+                        "gid": { "type": "integer", "maximum": 255 },
+                        "pid": { "type": "integer", "maximum": 255 },
+                        "text": {
+                            "type": "string",
+                            "description": "Any text if the format header \
+                                has `+escaped`, stored with `;`, newlines \
+                                and backslashes escaped"
+                        },
+                        "ts": {
+                            "type": "string",
+                            "description": "Defaults to the current time"
+                        }
+                    }
+                },
+                "TransactionProof": {
+                    "type": "object",
+                    "required": ["transaction"],
+                    "properties": {
+                        "transaction": { "type": "string" },
+                        "prev_hash": { "type": "string", "nullable": true }
+                    }
+                }
+            }
+        }
+    })
+}
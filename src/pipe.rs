@@ -0,0 +1,122 @@
+use std::io::{BufRead, Write};
+
+use crate::transaction::TransactionData;
+use crate::transaction_log::{FileError, TransactionLog};
+
+/// Creates a transaction for every `<gid>;<pid>;<text>` line of `input` and
+/// writes each stored one to `output`, so the log can be fed from a shell
+/// pipeline. Malformed lines are reported to `errors` and skipped, or end
+/// the pipe with `fail_fast`. Returns the number of skipped lines.
+pub fn pipe<L, R, W, E>(
+    log: &mut L,
+    input: R,
+    mut output: W,
+    mut errors: E,
+    fail_fast: bool,
+) -> Result<usize, FileError>
+where
+    L: TransactionLog<Error = FileError>,
+    R: BufRead,
+    W: Write,
+    E: Write,
+{
+    let policy = log.format()?.policy;
+    let mut skipped = 0;
+    for (i, line) in input.lines().enumerate() {
+        let line = line?;
+        if line.is_empty() {
+            continue;
+        }
+        let data = match TransactionData::parse_with_policy(&line, policy) {
+            Ok(data) => data,
+            Err(e) => {
+                let message = format!("line {}: {:?}", i + 1, e);
+                if fail_fast {
+                    return Err(FileError::Other(message));
+                }
+                writeln!(errors, "Skipping {}", message)?;
+                skipped += 1;
+                continue;
+            }
+        };
+        let tx = log.create(data, None)?;
+        writeln!(output, "{}", tx)?;
+    }
+    output.flush()?;
+    Ok(skipped)
+}
+
+
+#[cfg(test)]
+mod test {
+
+    use super::*;
+    use std::env;
+    use std::fs::{self, File};
+    use std::io::Cursor;
+    use std::path::PathBuf;
+    use std::process;
+    use crate::transaction_log::{GetAll, SimpleFileLog};
+
+    const INPUT: &'static str = "01;02;first\n\
+                                 bad line\n\
+                                 \n\
+                                 03;04;second\n";
+
+    fn temp_log(name: &str) -> PathBuf {
+        let path = env::temp_dir()
+            .join(format!("tx_pipe_{}_{}.txt", name, process::id()));
+        File::create(&path).unwrap();
+        path
+    }
+
+    #[test]
+    fn pipe_appends_lines() {
+        let path = temp_log("append");
+        let mut log = SimpleFileLog::new(&path);
+        let mut output = Vec::new();
+        let mut errors = Vec::new();
+        let skipped = pipe(
+            &mut log,
+            Cursor::new(INPUT),
+            &mut output,
+            &mut errors,
+            false,
+        ).unwrap();
+        assert_eq!(skipped, 1);
+        assert!(
+            String::from_utf8(errors)
+                .unwrap()
+                .starts_with("Skipping line 2: ")
+        );
+
+        let stored = SimpleFileLog::new(&path);
+        stored.verify_chain().unwrap();
+        let lines = stored
+            .get_all()
+            .unwrap()
+            .iter()
+            .map(|tx| format!("{}\n", tx))
+            .collect::<String>();
+        assert_eq!(String::from_utf8(output).unwrap(), lines);
+        assert_eq!(lines.lines().count(), 2);
+        fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn pipe_fails_fast() {
+        let path = temp_log("fail_fast");
+        let mut log = SimpleFileLog::new(&path);
+        let mut output = Vec::new();
+        match pipe(&mut log, Cursor::new(INPUT), &mut output, Vec::new(), true)
+        {
+            Err(FileError::Other(message)) => {
+                assert!(message.starts_with("line 2: "))
+            }
+            other => panic!("unexpected {:?}", other),
+        }
+        assert_eq!(String::from_utf8(output).unwrap().lines().count(), 1);
+        assert_eq!(SimpleFileLog::new(&path).get_all().unwrap().len(), 1);
+        fs::remove_file(&path).unwrap();
+    }
+}
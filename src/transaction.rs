@@ -1,11 +1,15 @@
+use std::cmp::Ordering;
+use std::collections::BTreeMap;
 use std::str::FromStr;
 use std::fmt;
 
-use chrono;
 use chrono::prelude::*;
 use sha2::{Digest, Sha256};
 
-#[derive(Debug, Clone)]
+use crate::clock;
+use crate::hex::{self, HexError};
+
+#[derive(Debug, Clone, PartialEq, Eq)]
 pub enum Error {
     IllegalArgument(String),
     ParseError(String),
@@ -14,21 +18,92 @@ pub enum Error {
 #[derive(Debug, Clone, PartialEq, Eq)]
 pub enum VerifyError {
     NonConsecutiveID(u32, u32),
-    MissmatchingHash(u32),
+    /// The stored hash of transaction `id` is `actual`, but its contents
+    /// hash to `expected`. Both are hex strings.
+    MissmatchingHash {
+        id: u32,
+        expected: String,
+        actual: String,
+    },
+}
+
+impl VerifyError {
+    /// Id of the transaction which failed to verify.
+    pub fn id(&self) -> u32 {
+        match *self {
+            VerifyError::NonConsecutiveID(_, id) => id,
+            VerifyError::MissmatchingHash { id, .. } => id,
+        }
+    }
 }
 
 
 #[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
 pub struct TransactionId(u32);
 
+/// Written in its `TimeFormat`, but compared and ordered by the instant
+/// only, consistent with `timestamp()`.
 #[derive(Debug, Clone)]
-pub struct TransactionTime(DateTime<chrono::FixedOffset>);
+pub struct TransactionTime(DateTime<chrono::FixedOffset>, TimeFormat);
+
+/// How `TransactionTime` is written in a log. It is part of every hash, so a
+/// log can only be read with the format it was written with.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TimeFormat {
+    /// `ddmmyy-HH:MM:SS` with a two digit year, the original format.
+    Compact,
+    /// `yyyy-mm-ddTHH:MM:SS`, ISO 8601 without an offset.
+    Iso,
+}
+
+/// How the transactions of a log are written. Unlike the `DataFormat`, which
+/// every line shows by its field widths, the format can't be told from a
+/// line, so a log records it in a header line and is parsed with it.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct LogFormat {
+    pub time: TimeFormat,
+    /// Policy of the texts, whose escapes are undone when parsing.
+    pub policy: CharPolicy,
+}
+
+/// Serialization format of `TransactionData`, identified by a version byte.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DataFormat {
+    /// Two digit gid and pid in `0..=99`, the original format.
+    V1,
+    /// Three digit gid and pid using the full `u8` range.
+    V2,
+}
+
+/// Which characters the text of `TransactionData` may contain.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CharPolicy {
+    /// No control characters at all, the text is serialized as is.
+    Strict,
+    /// Also allows `\t`. Tabs and backslashes are escaped as `\t` and `\\`
+    /// in the serialized form.
+    Relaxed,
+    /// Allows any text. Backslashes, `;`, `\n`, `\r`, `\t` and `\0` are
+    /// escaped as `\\`, `\s`, `\n`, `\r`, `\t` and `\0`, so the record
+    /// stays on one line and the hash covers the escaped form.
+    Escaped,
+}
+
+/// Group id of `TransactionData`, within the range of its `DataFormat`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct Gid(u8);
+
+/// Process id of `TransactionData`, within the range of its `DataFormat`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct Pid(u8);
 
 #[derive(Debug, Clone, PartialEq, Eq)]
 pub struct TransactionData {
-    gid: u8,
-    pid: u8,
+    gid: Gid,
+    pid: Pid,
     text: String,
+    format: DataFormat,
+    policy: CharPolicy,
 }
 
 #[derive(Debug, Clone)]
@@ -68,6 +143,41 @@ impl TransactionId {
             TransactionId(self.0 + 1)
         }
     }
+
+    /// Number of ids from `self` to `other`, in either direction.
+    pub fn distance(&self, other: &TransactionId) -> u32 {
+        self.0.abs_diff(other.0)
+    }
+}
+
+/// The ids from `from` up to and including `to`, empty if `to` is before
+/// `from`. Unlike `TransactionId::next` it never wraps around.
+pub fn id_range(from: TransactionId, to: TransactionId) -> IdRange {
+    IdRange { next: from.0, to: to.0 }
+}
+
+/// Iterator returned by `id_range`.
+#[derive(Debug, Clone)]
+pub struct IdRange {
+    next: u32,
+    to: u32,
+}
+
+impl Iterator for IdRange {
+    type Item = TransactionId;
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.next > self.to {
+            return None;
+        }
+        let id = TransactionId(self.next);
+        self.next += 1;
+        Some(id)
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        let len = (self.to + 1).saturating_sub(self.next) as usize;
+        (len, Some(len))
+    }
 }
 
 impl Default for TransactionId {
@@ -92,60 +202,519 @@ impl fmt::Display for TransactionId {
     }
 }
 
+impl TimeFormat {
+    /// `chrono` format string of the format.
+    pub fn pattern(&self) -> &'static str {
+        match *self {
+            TimeFormat::Compact => TransactionTime::FORMAT,
+            TimeFormat::Iso => "%Y-%m-%dT%H:%M:%S",
+        }
+    }
+
+}
+
+impl Default for TimeFormat {
+    fn default() -> Self {
+        TimeFormat::Compact
+    }
+}
+
+impl FromStr for TimeFormat {
+    type Err = Error;
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "compact" => Ok(TimeFormat::Compact),
+            "iso" => Ok(TimeFormat::Iso),
+            _ => Err(Error::IllegalArgument(
+                format!("Unknown time format `{}`", s),
+            )),
+        }
+    }
+}
+
+impl fmt::Display for TimeFormat {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            TimeFormat::Compact => write!(f, "compact"),
+            TimeFormat::Iso => write!(f, "iso"),
+        }
+    }
+}
+
+impl LogFormat {
+    /// `time` written in this format, or the current time if it is `None`.
+    pub fn stamp(&self, time: Option<TransactionTime>) -> TransactionTime {
+        time.unwrap_or_else(TransactionTime::current).in_format(self.time)
+    }
+}
+
+/// Space separated `key=value` pairs, e.g. `time=iso policy=escaped`.
+impl fmt::Display for LogFormat {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "time={} policy={}", self.time, self.policy)
+    }
+}
+
+/// Keys which are left out keep their default.
+impl FromStr for LogFormat {
+    type Err = Error;
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut format = LogFormat::default();
+        for pair in s.split_whitespace() {
+            let mut parts = pair.splitn(2, '=');
+            match (parts.next(), parts.next()) {
+                (Some("time"), Some(time)) => format.time = time.parse()?,
+                (Some("policy"), Some(policy)) => {
+                    format.policy = policy.parse()?
+                }
+                _ => {
+                    return Err(Error::ParseError(
+                        format!("Invalid log format `{}`", s),
+                    ))
+                }
+            }
+        }
+        Ok(format)
+    }
+}
+
 impl TransactionTime {
     pub const TZ_OFFSET: i32 = 1 * 3600;
     pub const FORMAT: &'static str = "%d%m%y-%H:%M:%S";
+    /// First year of the window two-digit years are mapped into.
+    pub const CENTURY: i32 = 2000;
 
+    /// The time of the clock of this thread, see `clock::set_clock`, in the
+    /// compact format.
     pub fn current() -> Self {
-        TransactionTime(Utc::now().with_timezone(
-            &FixedOffset::east(Self::TZ_OFFSET),
-        ))
+        TransactionTime(
+            clock::now().with_timezone(&FixedOffset::east(Self::TZ_OFFSET)),
+            TimeFormat::Compact,
+        )
     }
-}
 
-impl FromStr for TransactionTime {
-    type Err = Error;
-    fn from_str(s: &str) -> Result<Self, Self::Err> {
+    /// The same instant, written in `format`.
+    pub fn in_format(self, format: TimeFormat) -> Self {
+        TransactionTime(self.0, format)
+    }
+
+    pub fn format(&self) -> TimeFormat {
+        self.1
+    }
+
+    /// Parses `s` in `format`.
+    pub fn parse_with_format(
+        s: &str,
+        format: TimeFormat,
+    ) -> Result<Self, Error> {
+        match format {
+            TimeFormat::Compact => Self::from_str_in_century(s, Self::CENTURY),
+            TimeFormat::Iso => chrono::FixedOffset::east(Self::TZ_OFFSET)
+                .datetime_from_str(s, format.pattern())
+                .map(|t| TransactionTime(t, format))
+                .map_err(|_| {
+                    Error::ParseError(format!(
+                        "Could not parse time `{}` as {}",
+                        s,
+                        format.pattern()
+                    ))
+                }),
+        }
+    }
+
+    /// Parses the compact format, mapping the two-digit year into the
+    /// hundred years starting at `century`, e.g. `17` becomes 2017 for a
+    /// century of 2000 and 1917 for 1900. The format cannot tell centuries
+    /// apart, so logs spanning more than one window will not order correctly.
+    pub fn from_str_in_century(s: &str, century: i32) -> Result<Self, Error> {
+        let err = || {
+            Error::ParseError(
+                format!("Could not parse time `{}` ", s.to_owned()),
+            )
+        };
         let t = chrono::FixedOffset::east(Self::TZ_OFFSET)
             .datetime_from_str(s, Self::FORMAT)
+            .map_err(|_| err())?;
+        let year = century + t.year() % 100;
+        let t = t.with_year(year).ok_or_else(err)?;
+        Ok(TransactionTime(t, TimeFormat::Compact))
+    }
+
+    pub fn year(&self) -> i32 {
+        self.0.year()
+    }
+
+    pub fn datetime(&self) -> DateTime<FixedOffset> {
+        self.0
+    }
+
+    /// Seconds since the unix epoch.
+    pub fn timestamp(&self) -> i64 {
+        self.0.timestamp()
+    }
+
+    /// Parses an RFC 3339 / ISO 8601 timestamp, e.g.
+    /// `2017-10-04T10:00:00+01:00`. The time is converted to the storage
+    /// offset and truncated to seconds, so it still displays and hashes in
+    /// the compact format, or the one set with `in_format`.
+    pub fn from_rfc3339(s: &str) -> Result<Self, Error> {
+        let t = DateTime::parse_from_rfc3339(s)
             .map_err(|_| {
-                Error::ParseError(
-                    format!("Could not parse time `{}` ", s.to_owned()),
-                )
+                Error::ParseError(format!(
+                    "Could not parse RFC 3339 time `{}` ",
+                    s.to_owned()
+                ))
+            })?
+            .with_timezone(&FixedOffset::east(Self::TZ_OFFSET))
+            .with_nanosecond(0)
+            .ok_or_else(|| {
+                Error::ParseError(format!("Invalid time `{}` ", s.to_owned()))
             })?;
-        Ok(TransactionTime(t))
+        Ok(TransactionTime(t, TimeFormat::Compact))
     }
 }
 
+impl PartialEq for TransactionTime {
+    fn eq(&self, other: &Self) -> bool {
+        self.0 == other.0
+    }
+}
+
+impl Eq for TransactionTime {}
+
+impl PartialOrd for TransactionTime {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl Ord for TransactionTime {
+    fn cmp(&self, other: &Self) -> Ordering {
+        self.0.cmp(&other.0)
+    }
+}
+
+/// Parses the compact format, see `parse_with_format` for the others.
+impl FromStr for TransactionTime {
+    type Err = Error;
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Self::parse_with_format(s, TimeFormat::Compact)
+    }
+}
+
+/// Writes the format the time was parsed in or converted to.
 impl fmt::Display for TransactionTime {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
-        write!(f, "{}", self.0.format(Self::FORMAT))
+        write!(f, "{}", self.0.format(self.1.pattern()))
+    }
+}
+
+/// Time of the earliest of `txs`, which need not be in id order.
+pub fn earliest<'a, I>(txs: I) -> Option<&'a TransactionTime>
+where
+    I: IntoIterator<Item = &'a Transaction>,
+{
+    txs.into_iter().map(|t| t.ts()).min()
+}
+
+/// Time of the latest of `txs`, which need not be in id order.
+pub fn latest<'a, I>(txs: I) -> Option<&'a TransactionTime>
+where
+    I: IntoIterator<Item = &'a Transaction>,
+{
+    txs.into_iter().map(|t| t.ts()).max()
+}
+
+/// Number of transactions of each group among `txs`.
+pub fn gid_counts<'a, I>(txs: I) -> BTreeMap<Gid, usize>
+where
+    I: IntoIterator<Item = &'a Transaction>,
+{
+    let mut counts = BTreeMap::new();
+    for tx in txs {
+        *counts.entry(tx.data().gid()).or_insert(0) += 1;
+    }
+    counts
+}
+
+/// Which of the transactions of its group the transaction `id` is, starting
+/// at 1, or `None` if it is not among `txs`. Derived by counting the group's
+/// transactions up to `id`, nothing of it is stored.
+pub fn gid_seq<'a, I>(txs: I, id: &TransactionId) -> Option<usize>
+where
+    I: IntoIterator<Item = &'a Transaction>,
+{
+    let txs: Vec<&Transaction> = txs.into_iter().collect();
+    let gid = txs.iter().find(|t| t.id() == id)?.data().gid();
+    Some(
+        txs.iter()
+            .filter(|t| t.data().gid() == gid && t.id() <= id)
+            .count(),
+    )
+}
+
+
+impl DataFormat {
+    pub fn version(&self) -> u8 {
+        match *self {
+            DataFormat::V1 => 1,
+            DataFormat::V2 => 2,
+        }
+    }
+
+    pub fn from_version(version: u8) -> Result<Self, Error> {
+        match version {
+            1 => Ok(DataFormat::V1),
+            2 => Ok(DataFormat::V2),
+            v => Err(Error::IllegalArgument(
+                format!("Unknown data format version: {}", v),
+            )),
+        }
+    }
+
+    /// Number of digits of the gid and pid fields.
+    pub fn width(&self) -> usize {
+        match *self {
+            DataFormat::V1 => 2,
+            DataFormat::V2 => 3,
+        }
+    }
+
+    /// Format whose fields are `width` digits wide, V1 unless it's V2's.
+    fn for_width(width: usize) -> Self {
+        if width == DataFormat::V2.width() {
+            DataFormat::V2
+        } else {
+            DataFormat::V1
+        }
+    }
+
+    /// Largest gid and pid allowed.
+    pub fn max_value(&self) -> u8 {
+        match *self {
+            DataFormat::V1 => TransactionData::MAX_GID,
+            DataFormat::V2 => u8::max_value(),
+        }
+    }
+}
+
+impl Default for DataFormat {
+    fn default() -> Self {
+        DataFormat::V1
     }
 }
 
+impl CharPolicy {
+    /// Characters which break the line format and are never allowed.
+    const MANDATORY: &'static [char] = &[';', '\0', '\n'];
+
+    pub fn allows(&self, c: char) -> bool {
+        if *self == CharPolicy::Escaped {
+            return true;
+        }
+        if Self::MANDATORY.contains(&c) {
+            return false;
+        }
+        match (*self, c) {
+            (_, '\r') => false,
+            (CharPolicy::Strict, '\t') => false,
+            _ => true,
+        }
+    }
+
+    fn escape(&self, text: &str) -> String {
+        match *self {
+            CharPolicy::Strict => text.to_owned(),
+            CharPolicy::Relaxed => {
+                text.replace('\\', "\\\\").replace('\t', "\\t")
+            }
+            CharPolicy::Escaped => {
+                let mut out = String::with_capacity(text.len());
+                for c in text.chars() {
+                    match c {
+                        '\\' => out.push_str("\\\\"),
+                        ';' => out.push_str("\\s"),
+                        '\n' => out.push_str("\\n"),
+                        '\r' => out.push_str("\\r"),
+                        '\t' => out.push_str("\\t"),
+                        '\0' => out.push_str("\\0"),
+                        c => out.push(c),
+                    }
+                }
+                out
+            }
+        }
+    }
+
+    fn unescape(&self, text: &str) -> Result<String, Error> {
+        if *self == CharPolicy::Strict {
+            return Ok(text.to_owned());
+        }
+        let mut out = String::with_capacity(text.len());
+        let mut chars = text.chars();
+        while let Some(c) = chars.next() {
+            if c != '\\' {
+                out.push(c);
+                continue;
+            }
+            match (*self, chars.next()) {
+                (_, Some('t')) => out.push('\t'),
+                (_, Some('\\')) => out.push('\\'),
+                (CharPolicy::Escaped, Some('s')) => out.push(';'),
+                (CharPolicy::Escaped, Some('n')) => out.push('\n'),
+                (CharPolicy::Escaped, Some('r')) => out.push('\r'),
+                (CharPolicy::Escaped, Some('0')) => out.push('\0'),
+                _ => {
+                    return Err(Error::ParseError(
+                        format!("Invalid escape in text: `{}`", text),
+                    ))
+                }
+            }
+        }
+        Ok(out)
+    }
+}
+
+impl Default for CharPolicy {
+    fn default() -> Self {
+        CharPolicy::Strict
+    }
+}
+
+impl fmt::Display for CharPolicy {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            CharPolicy::Strict => write!(f, "strict"),
+            CharPolicy::Relaxed => write!(f, "relaxed"),
+            CharPolicy::Escaped => write!(f, "escaped"),
+        }
+    }
+}
+
+impl FromStr for CharPolicy {
+    type Err = Error;
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "strict" => Ok(CharPolicy::Strict),
+            "relaxed" => Ok(CharPolicy::Relaxed),
+            "escaped" => Ok(CharPolicy::Escaped),
+            _ => Err(Error::IllegalArgument(
+                format!("Unknown character policy `{}`", s),
+            )),
+        }
+    }
+}
+
+impl Gid {
+    /// Checks `gid` against the range of `DataFormat::V1`.
+    pub fn new(gid: u8) -> Result<Self, Error> {
+        Self::with_format(gid, DataFormat::V1)
+    }
+
+    pub fn with_format(gid: u8, format: DataFormat) -> Result<Self, Error> {
+        if gid < TransactionData::MIN_GID || gid > format.max_value() {
+            return Err(Error::IllegalArgument(format!("Invalid gid: {}", gid)));
+        }
+        Ok(Gid(gid))
+    }
+
+    pub fn inner(&self) -> u8 {
+        self.0
+    }
+}
+
+/// Three digits select the range of `DataFormat::V2`.
+impl FromStr for Gid {
+    type Err = Error;
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let gid = s.parse().map_err(
+            |_| Error::ParseError("Could not parse gid".to_owned()),
+        )?;
+        Self::with_format(gid, DataFormat::for_width(s.len()))
+    }
+}
+
+/// Honors width and fill, e.g. `{:03}`.
+impl fmt::Display for Gid {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        fmt::Display::fmt(&self.0, f)
+    }
+}
+
+impl Pid {
+    /// Checks `pid` against the range of `DataFormat::V1`.
+    pub fn new(pid: u8) -> Result<Self, Error> {
+        Self::with_format(pid, DataFormat::V1)
+    }
+
+    pub fn with_format(pid: u8, format: DataFormat) -> Result<Self, Error> {
+        if pid < TransactionData::MIN_PID || pid > format.max_value() {
+            return Err(Error::IllegalArgument(format!("Invalid pid: {}", pid)));
+        }
+        Ok(Pid(pid))
+    }
+
+    pub fn inner(&self) -> u8 {
+        self.0
+    }
+}
+
+/// Three digits select the range of `DataFormat::V2`.
+impl FromStr for Pid {
+    type Err = Error;
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let pid = s.parse().map_err(
+            |_| Error::ParseError("Could not parse pid".to_owned()),
+        )?;
+        Self::with_format(pid, DataFormat::for_width(s.len()))
+    }
+}
+
+/// Honors width and fill, e.g. `{:03}`.
+impl fmt::Display for Pid {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        fmt::Display::fmt(&self.0, f)
+    }
+}
 
 impl TransactionData {
     pub const MIN_GID: u8 = 0;
     pub const MAX_GID: u8 = 99;
     pub const MIN_PID: u8 = 0;
     pub const MAX_PID: u8 = 99;
-    const INVALID_CHAR: &'static [&'static str] =
-        &["\n", "\r", "\t", ";", "\0"];
 
     pub fn new<S: AsRef<str>>(
         gid: u8,
         pid: u8,
         text: S,
     ) -> Result<Self, Error> {
-        let text = text.as_ref();
+        Self::with_format(gid, pid, text, DataFormat::V1)
+    }
 
-        if gid < Self::MIN_GID || gid > Self::MAX_GID {
-            return Err(Error::IllegalArgument(format!("Invalid gid: {}", gid)));
-        }
-        if pid < Self::MIN_PID || pid > Self::MAX_PID {
-            return Err(Error::IllegalArgument(format!("Invalid pid: {}", pid)));
-        }
-        if Self::INVALID_CHAR.iter().any(|c| text.contains(c)) {
+    /// Like `new`, but the allowed gid and pid range and the field width
+    /// follow `format`.
+    pub fn with_format<S: AsRef<str>>(
+        gid: u8,
+        pid: u8,
+        text: S,
+        format: DataFormat,
+    ) -> Result<Self, Error> {
+        Self::with_policy(gid, pid, text, format, CharPolicy::Strict)
+    }
+
+    /// Like `with_format`, but the characters allowed in `text` follow
+    /// `policy`.
+    pub fn with_policy<S: AsRef<str>>(
+        gid: u8,
+        pid: u8,
+        text: S,
+        format: DataFormat,
+        policy: CharPolicy,
+    ) -> Result<Self, Error> {
+        let text = text.as_ref();
+        let gid = Gid::with_format(gid, format)?;
+        let pid = Pid::with_format(pid, format)?;
+        if !text.chars().all(|c| policy.allows(c)) {
             return Err(
                 Error::IllegalArgument(format!("Invalid text: `{}`", text)),
             );
@@ -155,136 +724,271 @@ impl TransactionData {
             gid,
             pid,
             text: text.to_owned(),
+            format,
+            policy,
         })
 
     }
 
-    pub fn gid(&self) -> u8 {
+    /// Data of the genesis transaction of the cluster `name`, which seeds the
+    /// hash chain with the cluster identity.
+    pub fn genesis<S: AsRef<str>>(name: S) -> Result<Self, Error> {
+        Self::new(Self::MIN_GID, Self::MIN_PID, name)
+    }
+
+    /// Builds the data from its already split `gid`, `pid` and `text` fields.
+    /// Three digit gid and pid fields select `DataFormat::V2`.
+    pub fn from_parts(gid: &str, pid: &str, text: &str) -> Result<Self, Error> {
+        Self::from_parts_with_policy(gid, pid, text, CharPolicy::Strict)
+    }
+
+    /// Like `from_parts`, but undoes the escapes of `policy` in `text`.
+    pub fn from_parts_with_policy(
+        gid: &str,
+        pid: &str,
+        text: &str,
+        policy: CharPolicy,
+    ) -> Result<Self, Error> {
+        let format = Self::detect_format(gid, pid);
+        let gid = Self::parse_gid(gid)?;
+        let pid = Self::parse_pid(pid)?;
+        let text = policy.unescape(text)?;
+        TransactionData::with_policy(gid, pid, text, format, policy)
+    }
+
+    fn detect_format(gid: &str, pid: &str) -> DataFormat {
+        if gid.len() == pid.len() {
+            DataFormat::for_width(gid.len())
+        } else {
+            DataFormat::V1
+        }
+    }
+
+    fn parse_gid(gid: &str) -> Result<u8, Error> {
+        gid.parse().map_err(
+            |_| Error::ParseError("Could not parse gid".to_owned()),
+        )
+    }
+
+    fn parse_pid(pid: &str) -> Result<u8, Error> {
+        pid.parse().map_err(
+            |_| Error::ParseError("Could not parse pid".to_owned()),
+        )
+    }
+
+    pub fn gid(&self) -> Gid {
         self.gid
     }
 
-    pub fn pid(&self) -> u8 {
+    pub fn pid(&self) -> Pid {
         self.pid
     }
 
     pub fn text<'a>(&'a self) -> &'a str {
         self.text.as_str()
     }
-}
 
-impl FromStr for TransactionData {
-    type Err = Error;
-    fn from_str(s: &str) -> Result<Self, Self::Err> {
-        //println!("Parsing for data: {}", s);
+    /// UTF-8 bytes of the text as they enter the hash, i.e. escaped by the
+    /// policy.
+    pub fn text_bytes(&self) -> Vec<u8> {
+        self.policy.escape(&self.text).into_bytes()
+    }
+
+    pub fn format(&self) -> DataFormat {
+        self.format
+    }
+
+    pub fn policy(&self) -> CharPolicy {
+        self.policy
+    }
+
+    /// Returns a copy with the group id changed to `gid`, checked against the
+    /// format of this data. A `Transaction` built from the old data keeps its
+    /// hash, so the caller has to build a new transaction from the copy.
+    pub fn with_gid(&self, gid: u8) -> Result<Self, Error> {
+        let pid = self.pid.inner();
+        Self::with_policy(gid, pid, &self.text, self.format, self.policy)
+    }
+
+    /// Returns a copy written with `policy`, if it allows the text, see
+    /// `with_gid`.
+    pub fn in_policy(&self, policy: CharPolicy) -> Result<Self, Error> {
+        let (gid, pid) = (self.gid.inner(), self.pid.inner());
+        Self::with_policy(gid, pid, &self.text, self.format, policy)
+    }
+
+    /// Returns a copy with the process id changed to `pid`, see `with_gid`.
+    pub fn with_pid(&self, pid: u8) -> Result<Self, Error> {
+        let gid = self.gid.inner();
+        Self::with_policy(gid, pid, &self.text, self.format, self.policy)
+    }
+
+    /// Parses data serialized under `policy`, undoing its escapes.
+    /// `FromStr` parses with `CharPolicy::Strict`.
+    pub fn parse_with_policy(
+        s: &str,
+        policy: CharPolicy,
+    ) -> Result<Self, Error> {
         let mut parts = s.split(";");
-        let gid: u8 =
-            parts
-                .next()
-                .ok_or(Error::ParseError("Incomplete data".to_owned()))?
-                .parse()
-                .map_err(
-                    |_| Error::ParseError("Could not parse gid".to_owned()),
-                )?;
-        let pid: u8 =
-            parts
-                .next()
-                .ok_or(Error::ParseError("Incomplete data".to_owned()))?
-                .parse()
-                .map_err(
-                    |_| Error::ParseError("Could not parse pid".to_owned()),
-                )?;
-        let text = parts.next().ok_or(
-            Error::ParseError("Incomplete data".to_owned()),
-        )?;
+        let err = Error::ParseError("Incomplete data".to_owned());
+        let gid_str = parts.next().ok_or_else(|| err.clone())?;
+        let gid = Self::parse_gid(gid_str)?;
+        let pid_str = parts.next().ok_or_else(|| err.clone())?;
+        let pid = Self::parse_pid(pid_str)?;
+        let text = parts.next().ok_or_else(|| err.clone())?;
         if parts.next().is_some() {
             return Err(Error::ParseError("Too much data".to_owned()));
         }
-        Ok(TransactionData::new(gid, pid, text)?)
+        let format = Self::detect_format(gid_str, pid_str);
+        let text = policy.unescape(text)?;
+        Ok(TransactionData::with_policy(gid, pid, text, format, policy)?)
+    }
+}
+
+impl FromStr for TransactionData {
+    type Err = Error;
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Self::parse_with_policy(s, CharPolicy::Strict)
     }
 }
 
 impl fmt::Display for TransactionData {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
-        write!(f, "{:02};{:02};{}", self.gid, self.pid, &self.text)
+        write!(
+            f,
+            "{:0w$};{:0w$};{}",
+            self.gid,
+            self.pid,
+            self.policy.escape(&self.text),
+            w = self.format.width()
+        )
     }
 }
 
 
 impl TransactionHash {
+    /// Length of a hash in bytes.
+    pub const LEN: usize = 32;
+
     fn new(
         id: &TransactionId,
         ts: &TransactionTime,
         data: &TransactionData,
-        prev: Option<&Transaction>,
+        prev: Option<&TransactionHash>,
     ) -> Self {
         let mut hasher = Sha256::default();
-        let s = format!("{};{};{};", id, ts, data);
-        //println!("Hashing {}", &s);
-        hasher.input(s.as_bytes());
-        if prev.is_some() {
-            let p = format!("{}", prev.unwrap().hash());
-            //println!("Hashing {}", &p);
-            hasher.input(p.as_bytes());
-        }
+        hasher.input(canonical_preimage(id, ts, data, prev).as_bytes());
         let hash = Vec::from(hasher.result().as_slice());
-        let hash_str = format!("{:X}", hasher.result());
         TransactionHash {
+            string: hex::encode_upper(&hash),
             vec: hash,
-            string: hash_str,
         }
+    }
 
+    /// The hash of the raw `bytes`, which must be `LEN` long.
+    pub fn from_slice(bytes: &[u8]) -> Result<Self, Error> {
+        if bytes.len() != TransactionHash::LEN {
+            return Err(Error::IllegalArgument(
+                format!("Invalid hash length: {}", bytes.len()),
+            ));
+        }
+        Ok(TransactionHash {
+            vec: bytes.to_vec(),
+            string: hex::encode_upper(bytes),
+        })
     }
 
     pub fn as_slice<'a>(&'a self) -> &'a [u8] {
         self.vec.as_slice()
     }
+
+    /// The first `chars` characters of the hex string, or all of them if
+    /// `chars` exceeds its length.
+    pub fn short<'a>(&'a self, chars: usize) -> &'a str {
+        &self.string[..chars.min(self.string.len())]
+    }
 }
 
 impl FromStr for TransactionHash {
     type Err = Error;
     fn from_str(s: &str) -> Result<Self, Self::Err> {
-        let mut hash = Vec::with_capacity(32);
-        let mut hash_chars = s.trim().chars();
-        //FIXME: ugly way to parse hex to Vec<u8>
+        let hex = s.trim();
         //check for uppercase in hexstring
-        if hash_chars.clone().any(
-            |c| !(c.is_uppercase() || c.is_numeric()),
-        )
-        {
+        if hex.chars().any(|c| !(c.is_uppercase() || c.is_numeric())) {
             return Err(
                 Error::ParseError("Invalid hash character case".to_owned()),
             );
         }
-        loop {
-            match (hash_chars.next(), hash_chars.next()) {
-                (Some(c1), Some(c2)) => {
-                    hash.push(u8::from_str_radix(&format!("{}{}", c1, c2), 16)
-                        .map_err(|_|
-                            Error::ParseError("Invalid hash HEX".to_owned())
-                        )?
-                    )
-                }
-                (Some(_), None) => {
-                    return Err(
-                        Error::ParseError("Invalid hash length".to_owned()),
-                    )
-                }
-                (None, _) => break,
+        let hash = hex::decode(hex).map_err(|e| match e {
+            HexError::InvalidChar(_) => {
+                Error::ParseError("Invalid hash HEX".to_owned())
             }
-        }
+            HexError::OddLength => {
+                Error::ParseError("Invalid hash length".to_owned())
+            }
+        })?;
         Ok(TransactionHash {
             vec: hash,
-            string: s.to_owned(),
+            string: hex.to_owned(),
         })
     }
 }
 
+impl From<[u8; 32]> for TransactionHash {
+    fn from(bytes: [u8; 32]) -> Self {
+        TransactionHash::from_slice(&bytes[..])
+            .expect("32 bytes are a valid hash length")
+    }
+}
+
 impl fmt::Display for TransactionHash {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
         write!(f, "{}", &self.string)
     }
 }
 
+fn sha256(parts: &[&[u8]]) -> Vec<u8> {
+    let mut hasher = Sha256::default();
+    for part in parts {
+        hasher.input(part);
+    }
+    Vec::from(hasher.result().as_slice())
+}
+
+/// Root of a binary Merkle tree over the hashes of `txs`, in order. Leaves
+/// hash `0x00` and a transaction hash, inner nodes hash `0x01` and their two
+/// children, an odd node moves up a level unchanged. No transactions give
+/// the SHA-256 of nothing.
+///
+/// The hash of the last transaction already depends on every earlier one,
+/// but checking that a single transaction belongs to it means recomputing
+/// the chain from the start. The root can be checked against one
+/// transaction with the log2(n) hashes beside its path, so it suits
+/// anchoring the log somewhere external.
+pub fn merkle_root<I>(txs: I) -> TransactionHash
+where
+    I: IntoIterator<Item = Transaction>,
+{
+    let mut level: Vec<Vec<u8>> = txs
+        .into_iter()
+        .map(|t| sha256(&[&[0], t.hash().as_slice()]))
+        .collect();
+    if level.is_empty() {
+        level.push(sha256(&[]));
+    }
+    while level.len() > 1 {
+        level = level
+            .chunks(2)
+            .map(|pair| match pair.len() {
+                2 => sha256(&[&[1], &pair[0], &pair[1]]),
+                _ => pair[0].clone(),
+            })
+            .collect();
+    }
+    TransactionHash::from_slice(level[0].as_slice())
+        .expect("SHA-256 has the length of a hash")
+}
+
 impl Transaction {
     pub fn new(
         id: TransactionId,
@@ -292,7 +996,17 @@ impl Transaction {
         data: TransactionData,
         prev: Option<&Transaction>,
     ) -> Self {
-        let hash = TransactionHash::new(&id, &ts, &data, prev);
+        Self::with_prev_hash(id, ts, data, prev.map(|p| p.hash()))
+    }
+
+    /// Like `new`, but only needs the hash of the previous transaction.
+    pub fn with_prev_hash(
+        id: TransactionId,
+        ts: TransactionTime,
+        data: TransactionData,
+        prev_hash: Option<&TransactionHash>,
+    ) -> Self {
+        let hash = TransactionHash::new(&id, &ts, &data, prev_hash);
         Transaction { id, ts, data, hash }
     }
 
@@ -311,22 +1025,75 @@ impl Transaction {
     pub fn hash(&self) -> &TransactionHash {
         &self.hash
     }
+
+    /// Time since `ts` by the clock of this thread, negative if `ts` lies in
+    /// the future.
+    pub fn age(&self) -> chrono::Duration {
+        clock::now().signed_duration_since(self.ts.0)
+    }
+
+    /// Whether the transaction is older than `age`, e.g. to expire it. A
+    /// transaction from the future is older than no positive `age`.
+    pub fn is_older_than(&self, age: chrono::Duration) -> bool {
+        self.age() > age
+    }
+
+    /// Number of bytes the transaction takes up in a log file, including the
+    /// trailing newline.
+    pub fn line_len(&self) -> usize {
+        self.to_string().len() + 1
+    }
+
+    /// Describes why the hash of this transaction does or doesn't verify
+    /// after `prev`, including the preimage the expected hash was built from.
+    pub fn explain_mismatch(&self, prev: Option<&Transaction>) -> String {
+        let prev_hash = prev.map(|p| p.hash());
+        let expected =
+            TransactionHash::new(&self.id, &self.ts, &self.data, prev_hash);
+        if expected.as_slice() == self.hash.as_slice() {
+            return format!("Hash of transaction {} matches", self.id);
+        }
+        format!(
+            "Hash mismatch in transaction {}: stored {}, expected {} \
+             from preimage `{}`",
+            self.id,
+            self.hash,
+            expected,
+            canonical_preimage(&self.id, &self.ts, &self.data, prev_hash)
+        )
+    }
 }
 
-impl FromStr for Transaction {
-    type Err = Error;
-    fn from_str(s: &str) -> Result<Self, Self::Err> {
+impl Transaction {
+    /// Parses untrusted input, e.g. a line of a foreign log. Never panics,
+    /// whatever the input; this is the entry point of the
+    /// `parse_transaction` fuzz target.
+    pub fn try_parse(s: &str) -> Result<Transaction, Error> {
+        s.parse()
+    }
+
+    /// Parses a line of a log written in `format`.
+    pub fn parse_with_format(
+        s: &str,
+        format: LogFormat,
+    ) -> Result<Transaction, Error> {
         let mut parts = s.split(";");
         let err = Error::ParseError("Incomplete data".to_owned());
         let id: TransactionId =
             parts.next().ok_or_else(|| err.clone())?.parse()?;
-        let ts: TransactionTime =
-            parts.next().ok_or_else(|| err.clone())?.parse()?;
+        let ts = TransactionTime::parse_with_format(
+            parts.next().ok_or_else(|| err.clone())?,
+            format.time,
+        )?;
         let data_gid = parts.next().ok_or_else(|| err.clone())?;
         let data_pid = parts.next().ok_or_else(|| err.clone())?;
         let data_text = parts.next().ok_or_else(|| err.clone())?;
-        let data: TransactionData =
-            format!("{};{};{}", data_gid, data_pid, data_text).parse()?;
+        let data = TransactionData::from_parts_with_policy(
+            data_gid,
+            data_pid,
+            data_text,
+            format.policy,
+        )?;
         let hash: TransactionHash =
             parts.next().ok_or_else(|| err.clone())?.parse()?;
         if parts.next().is_some() {
@@ -336,6 +1103,14 @@ impl FromStr for Transaction {
     }
 }
 
+/// Parses a line of a log in the default `LogFormat`.
+impl FromStr for Transaction {
+    type Err = Error;
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Self::parse_with_format(s, LogFormat::default())
+    }
+}
+
 impl fmt::Display for Transaction {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
         write!(
@@ -350,6 +1125,20 @@ impl fmt::Display for Transaction {
 }
 
 
+/// The string hashed for a transaction: its id, timestamp and data, followed
+/// by the hash of the previous transaction if there is one.
+pub fn canonical_preimage(
+    id: &TransactionId,
+    ts: &TransactionTime,
+    data: &TransactionData,
+    prev: Option<&TransactionHash>,
+) -> String {
+    match prev {
+        Some(prev) => format!("{};{};{};{}", id, ts, data, prev),
+        None => format!("{};{};{};", id, ts, data),
+    }
+}
+
 pub fn verify_transaction(
     tx: &Transaction,
     prev: Option<&Transaction>,
@@ -362,9 +1151,23 @@ pub fn verify_transaction(
             ));
         }
     }
-    let hash = TransactionHash::new(tx.id(), tx.ts(), tx.data(), prev);
+    verify_with_prev_hash(tx, prev.map(|p| p.hash()))
+}
+
+/// Checks the hash of `tx` given only the hash of its predecessor, which is
+/// `None` for the first transaction. Unlike `verify_transaction` this can't
+/// check that the ids are consecutive.
+pub fn verify_with_prev_hash(
+    tx: &Transaction,
+    prev_hash: Option<&TransactionHash>,
+) -> Result<(), VerifyError> {
+    let hash = TransactionHash::new(tx.id(), tx.ts(), tx.data(), prev_hash);
     if tx.hash().as_slice() != hash.as_slice() {
-        return Err(VerifyError::MissmatchingHash(tx.id().inner()));
+        return Err(VerifyError::MissmatchingHash {
+            id: tx.id().inner(),
+            expected: hash.to_string(),
+            actual: tx.hash().to_string(),
+        });
     }
     Ok(())
 }
@@ -392,7 +1195,8 @@ mod test {
         let input = "00000001;041017-10:00:00;00;01;Testü;267C4D5033ED7F96B43216FD8C871E4B96F1221204312AD6F43362F2D12C9B29\n";
         let parsed: Result<Transaction, _> = input.parse();
         assert!(parsed.is_ok());
-        assert_eq!(parsed.unwrap().to_string(), input);
+        // the line break around the hash is dropped
+        assert_eq!(parsed.unwrap().to_string(), input.trim_end());
     }
 
     #[test]
@@ -408,6 +1212,509 @@ mod test {
         assert_eq!(parsed.unwrap().to_string(), tx.to_string());
     }
 
+    #[test]
+    fn parse_data() {
+        let data: TransactionData = "05;06;hello world".parse().unwrap();
+        assert_eq!(data, TransactionData::new(5, 6, "hello world").unwrap());
+        assert_eq!(
+            TransactionData::from_parts("05", "06", "hello world").unwrap(),
+            data
+        );
+        match "05;xx;hello".parse::<TransactionData>() {
+            Err(Error::ParseError(ref e)) => assert_eq!(e, "Could not parse pid"),
+            other => panic!("unexpected {:?}", other),
+        }
+    }
+
+    #[test]
+    fn data_format_v2() {
+        let data =
+            TransactionData::with_format(7, 150, "wide", DataFormat::V2)
+                .unwrap();
+        assert_eq!(data.to_string(), "007;150;wide");
+        let parsed: TransactionData = data.to_string().parse().unwrap();
+        assert_eq!(parsed, data);
+        assert_eq!(parsed.format(), DataFormat::V2);
+
+        let tx = Transaction::new(
+            TransactionId::new(1).unwrap(),
+            "041017-10:00:00".parse::<TransactionTime>().unwrap(),
+            data,
+            None,
+        );
+        let parsed: Transaction = tx.to_string().parse().unwrap();
+        assert_eq!(parsed.to_string(), tx.to_string());
+        assert_eq!(verify_transaction(&parsed, None), Ok(()));
+
+        let legacy: TransactionData = "05;06;narrow".parse().unwrap();
+        assert_eq!(legacy.format(), DataFormat::V1);
+        assert_eq!(legacy.to_string(), "05;06;narrow");
+        assert!(TransactionData::new(7, 150, "narrow").is_err());
+        assert!("05;150;mixed".parse::<TransactionData>().is_err());
+        assert_eq!(
+            DataFormat::from_version(DataFormat::V2.version()),
+            Ok(DataFormat::V2)
+        );
+    }
+
+    #[test]
+    fn parse_incomplete_and_too_much() {
+        match "xx".parse::<TransactionData>() {
+            Err(Error::ParseError(ref e)) => assert_eq!(e, "Could not parse gid"),
+            other => panic!("unexpected {:?}", other),
+        }
+        match "05;06".parse::<TransactionData>() {
+            Err(Error::ParseError(ref e)) => assert_eq!(e, "Incomplete data"),
+            other => panic!("unexpected {:?}", other),
+        }
+        match "05;06;hello;world".parse::<TransactionData>() {
+            Err(Error::ParseError(ref e)) => assert_eq!(e, "Too much data"),
+            other => panic!("unexpected {:?}", other),
+        }
+        match "00000001;041017-10:00:00;00;01".parse::<Transaction>() {
+            Err(Error::ParseError(ref e)) => assert_eq!(e, "Incomplete data"),
+            other => panic!("unexpected {:?}", other),
+        }
+        let input = "00000001;041017-10:00:00;00;01;Testü;267C4D5033ED7F96B43216FD8C871E4B96F1221204312AD6F43362F2D12C9B29;";
+        match input.parse::<Transaction>() {
+            Err(Error::ParseError(ref e)) => assert_eq!(e, "Too much data"),
+            other => panic!("unexpected {:?}", other),
+        }
+    }
+
+    #[test]
+    fn parse_rfc3339() {
+        let compact: TransactionTime = "041017-10:00:00".parse().unwrap();
+        for input in &[
+            "2017-10-04T10:00:00+01:00",
+            "2017-10-04T09:00:00Z",
+            "2017-10-04T11:00:00.750+02:00",
+        ] {
+            let t = TransactionTime::from_rfc3339(input).unwrap();
+            assert_eq!(t.to_string(), "041017-10:00:00");
+            assert_eq!(t.0, compact.0);
+        }
+        assert!(TransactionTime::from_rfc3339("041017-10:00:00").is_err());
+    }
+
+    #[test]
+    fn two_digit_years() {
+        for &(input, year) in &[
+            ("010100-00:00:00", 2000),
+            ("010169-00:00:00", 2069),
+            ("010170-00:00:00", 2070),
+            ("311299-23:59:59", 2099),
+        ] {
+            let t: TransactionTime = input.parse().unwrap();
+            assert_eq!(t.year(), year);
+            assert_eq!(t.to_string(), input);
+        }
+        let t = TransactionTime::from_str_in_century("311299-23:59:59", 1900);
+        assert_eq!(t.unwrap().year(), 1999);
+        assert!(TransactionTime::from_str_in_century("290200-00:00:00", 1900)
+            .is_err());
+    }
+
+    #[test]
+    fn iso_time_format() {
+        let compact = TransactionTime::from_str_in_century(
+            "041017-10:00:00",
+            TransactionTime::CENTURY,
+        ).unwrap();
+        let iso = LogFormat {
+            time: TimeFormat::Iso,
+            ..LogFormat::default()
+        };
+        let t = TransactionTime::parse_with_format(
+            "2017-10-04T10:00:00",
+            TimeFormat::Iso,
+        ).unwrap();
+        assert_eq!(t, compact);
+        assert_eq!(t.to_string(), "2017-10-04T10:00:00");
+        assert_eq!(compact.clone().in_format(TimeFormat::Iso).to_string(),
+                   "2017-10-04T10:00:00");
+        assert!(
+            TransactionTime::parse_with_format("041017-10:00:00", iso.time)
+                .is_err()
+        );
+
+        let tx = Transaction::new(
+            TransactionId::new(1).unwrap(),
+            t,
+            TransactionData::new(0, 1, "Testü").unwrap(),
+            None,
+        );
+        let line = tx.to_string();
+        assert!(line.starts_with("00000001;2017-10-04T10:00:00;00;01;"));
+        let parsed = Transaction::parse_with_format(&line, iso).unwrap();
+        assert_eq!(parsed.to_string(), line);
+        assert_eq!(verify_transaction(&parsed, None), Ok(()));
+
+        // the compact format refuses the line, and hashes differently
+        assert!(line.parse::<Transaction>().is_err());
+        let compact_tx = Transaction::new(
+            TransactionId::new(1).unwrap(),
+            compact,
+            TransactionData::new(0, 1, "Testü").unwrap(),
+            None,
+        );
+        assert!(!line.ends_with(&compact_tx.hash().to_string()));
+
+        assert_eq!("iso".parse(), Ok(TimeFormat::Iso));
+        assert!("ISO".parse::<TimeFormat>().is_err());
+        assert_eq!(iso.to_string().parse(), Ok(iso));
+        assert_eq!(
+            "policy=escaped".parse::<LogFormat>().map(|f| f.policy),
+            Ok(CharPolicy::Escaped)
+        );
+        assert_eq!("".parse(), Ok(LogFormat::default()));
+        assert!("time".parse::<LogFormat>().is_err());
+    }
+
+    #[test]
+    fn time_accessors() {
+        let times: Vec<TransactionTime> =
+            ["311217-23:59:59", "010118-00:00:00", "010118-00:00:01"]
+                .iter()
+                .map(|t| t.parse().unwrap())
+                .collect();
+        assert!(times[0].timestamp() < times[1].timestamp());
+        assert_eq!(times[1].timestamp() + 1, times[2].timestamp());
+
+        let t = &times[0];
+        let parsed: TransactionTime = t.to_string().parse().unwrap();
+        assert_eq!(parsed.datetime(), t.datetime());
+        assert_eq!(t.datetime().timestamp(), t.timestamp());
+    }
+
+    #[test]
+    fn time_ordering() {
+        let time = |t: &str| t.parse::<TransactionTime>().unwrap();
+        let mut times = vec![
+            time("010118-00:00:01"),
+            time("311217-23:59:59"),
+            time("010118-00:00:00"),
+        ];
+        times.sort();
+        let sorted: Vec<String> = times.iter().map(|t| t.to_string()).collect();
+        assert_eq!(
+            sorted,
+            vec!["311217-23:59:59", "010118-00:00:00", "010118-00:00:01"]
+        );
+        for pair in times.windows(2) {
+            assert!(pair[0] < pair[1]);
+            assert!(pair[0].timestamp() < pair[1].timestamp());
+        }
+        assert_eq!(times.iter().min(), Some(&time("311217-23:59:59")));
+        assert_eq!(
+            TransactionTime::from_rfc3339("2017-12-31T22:59:59Z").unwrap(),
+            time("311217-23:59:59")
+        );
+
+        let data = TransactionData::new(1, 2, "timed").unwrap();
+        let first = Transaction::new(
+            TransactionId::new(1).unwrap(),
+            time("010118-00:00:00"),
+            data.clone(),
+            None,
+        );
+        let second = Transaction::new(
+            TransactionId::new(2).unwrap(),
+            time("311217-23:59:59"),
+            data,
+            Some(&first),
+        );
+        let txs = vec![first, second];
+        assert_eq!(earliest(&txs), Some(&time("311217-23:59:59")));
+        assert_eq!(latest(&txs), Some(&time("010118-00:00:00")));
+        assert_eq!(latest(&Vec::new()), None);
+    }
+
+    #[test]
+    fn try_parse_malformed() {
+        let hash =
+            "267C4D5033ED7F96B43216FD8C871E4B96F1221204312AD6F43362F2D12C9B29";
+        let inputs = vec![
+            "".to_owned(),
+            ";".to_owned(),
+            ";;;;;".to_owned(),
+            ";;;;;;".to_owned(),
+            "\0;\n".to_owned(),
+            format!("00000001;041017-10:00:00;01;02;text;{}Ä", hash),
+            format!("00000001;041017-10:00:00;01;02;text;ÄÖ{}", hash),
+            format!("00000001;041017-10:00:00;01;02;text;{}", "٣".repeat(64)),
+            format!("0000000١;041017-10:00:00;01;02;text;{}", hash),
+            format!("00000001;0410é7-10:00:00;01;02;text;{}", hash),
+            format!("00000001;041017-10:00:00;0é;02;text;{}", hash),
+            format!("00000001;041017-10:00:00;01;02;text;{};", hash),
+        ];
+        for input in &inputs {
+            assert!(Transaction::try_parse(input).is_err(), "{}", input);
+        }
+
+        // unicode whitespace around the hash is tolerated and dropped
+        let padded =
+            format!("00000001;041017-10:00:00;01;02;text;\u{3000}{}", hash);
+        let tx = Transaction::try_parse(&padded).unwrap();
+        assert_eq!(tx.hash().short(2), &hash[..2]);
+        assert_eq!(tx.to_string(), padded.replace('\u{3000}', ""));
+    }
+
+    #[test]
+    fn text_bytes() {
+        let data = TransactionData::new(1, 2, "Grüße, 世界 🌍").unwrap();
+        assert_eq!(data.text_bytes(), data.text().as_bytes());
+        assert_eq!(data.text_bytes().len(), 20);
+        assert_eq!(&data.text_bytes()[2..4], "ü".as_bytes());
+        let line = data.to_string();
+        assert!(line.as_bytes().ends_with(&data.text_bytes()));
+
+        let escaped = TransactionData::with_policy(
+            1,
+            2,
+            "a;b\n",
+            DataFormat::V1,
+            CharPolicy::Escaped,
+        ).unwrap();
+        assert_eq!(escaped.text_bytes(), br"a\sb\n".to_vec());
+        let preimage = canonical_preimage(
+            &TransactionId::new(1).unwrap(),
+            &"041017-10:00:00".parse().unwrap(),
+            &escaped,
+            None,
+        );
+        let mut field = escaped.text_bytes();
+        field.push(b';');
+        assert!(preimage.as_bytes().ends_with(&field));
+    }
+
+    #[test]
+    fn short_hash() {
+        let hash: TransactionHash =
+            "267C4D5033ED7F96B43216FD8C871E4B96F1221204312AD6F43362F2D12C9B29"
+                .parse()
+                .unwrap();
+        assert_eq!(hash.short(8), "267C4D50");
+        assert_eq!(hash.short(0), "");
+        assert_eq!(hash.short(100), hash.to_string());
+    }
+
+    #[test]
+    fn hash_from_bytes() {
+        let hex = "267C4D5033ED7F96B43216FD8C871E4B96F1221204312AD6F43362F2D12C9B29";
+        let parsed: TransactionHash = hex.parse().unwrap();
+        let mut bytes = [0u8; 32];
+        bytes.copy_from_slice(parsed.as_slice());
+
+        let hash = TransactionHash::from(bytes);
+        assert_eq!(hash.to_string(), hex);
+        assert_eq!(hash.as_slice(), parsed.as_slice());
+        let hash = TransactionHash::from_slice(&bytes[..]).unwrap();
+        assert_eq!(hash.to_string(), hex);
+
+        assert_eq!(
+            TransactionHash::from_slice(&bytes[..31]).unwrap_err(),
+            Error::IllegalArgument("Invalid hash length: 31".to_owned())
+        );
+    }
+
+    #[test]
+    fn illegal_arguments() {
+        assert_eq!(
+            TransactionId::new(0),
+            Err(Error::IllegalArgument("Invalid id: 0".to_owned()))
+        );
+        assert_eq!(
+            TransactionId::new(TransactionId::MAX_ID + 1),
+            Err(Error::IllegalArgument("Invalid id: 100000000".to_owned()))
+        );
+        assert_eq!(
+            TransactionData::new(100, 1, "Test"),
+            Err(Error::IllegalArgument("Invalid gid: 100".to_owned()))
+        );
+        assert_eq!(
+            TransactionData::new(1, 100, "Test"),
+            Err(Error::IllegalArgument("Invalid pid: 100".to_owned()))
+        );
+        assert_eq!(
+            TransactionData::new(1, 1, "Te;st"),
+            Err(Error::IllegalArgument("Invalid text: `Te;st`".to_owned()))
+        );
+    }
+
+    #[test]
+    fn id_distance_and_range() {
+        let id = |id: u32| TransactionId::new(id).unwrap();
+        assert_eq!(id(3).distance(&id(10)), 7);
+        assert_eq!(id(10).distance(&id(3)), 7);
+        assert_eq!(id(5).distance(&id(5)), 0);
+        let max = id(TransactionId::MAX_ID);
+        assert_eq!(id(TransactionId::MIN_ID).distance(&max), 99_999_998);
+
+        let ids: Vec<u32> = id_range(id(3), id(6)).map(|i| i.inner()).collect();
+        assert_eq!(ids, vec![3, 4, 5, 6]);
+        assert_eq!(id_range(id(3), id(6)).size_hint(), (4, Some(4)));
+        assert_eq!(id_range(id(5), id(5)).count(), 1);
+        assert_eq!(id_range(id(6), id(3)).count(), 0);
+        assert_eq!(id_range(id(6), id(3)).size_hint(), (0, Some(0)));
+
+        let last: Vec<_> = id_range(id(TransactionId::MAX_ID - 1), max)
+            .map(|i| i.inner())
+            .collect();
+        assert_eq!(last, vec![99_999_998, 99_999_999]);
+    }
+
+    #[test]
+    fn gid_and_pid_ranges() {
+        assert_eq!(
+            Gid::new(100),
+            Err(Error::IllegalArgument("Invalid gid: 100".to_owned()))
+        );
+        assert!(Pid::new(100).is_err());
+        assert_eq!(Gid::with_format(200, DataFormat::V2).unwrap().inner(), 200);
+        assert_eq!("07".parse::<Gid>(), Gid::new(7));
+        assert!("100".parse::<Gid>().is_ok());
+        assert!("99x".parse::<Pid>().is_err());
+        assert_eq!(format!("{:03}", Pid::new(7).unwrap()), "007");
+
+        let data = TransactionData::new(5, 6, "typed").unwrap();
+        assert_eq!(data.gid(), Gid::new(5).unwrap());
+        assert_eq!(data.pid().inner(), 6);
+        assert!(TransactionData::new(100, 6, "out of range").is_err());
+    }
+
+    #[test]
+    fn change_gid_and_pid() {
+        let data = TransactionData::new(5, 6, "moved").unwrap();
+        let regrouped = data.with_gid(7).unwrap();
+        assert_eq!(regrouped.to_string(), "07;06;moved");
+        assert_eq!(data.with_pid(8).unwrap().to_string(), "05;08;moved");
+        assert_eq!(data.to_string(), "05;06;moved");
+        assert_eq!(
+            data.with_gid(100),
+            Err(Error::IllegalArgument("Invalid gid: 100".to_owned()))
+        );
+        assert!(data.with_pid(100).is_err());
+
+        let wide = TransactionData::with_format(5, 6, "wide", DataFormat::V2)
+            .unwrap();
+        assert_eq!(wide.with_gid(200).unwrap().to_string(), "200;006;wide");
+    }
+
+    #[test]
+    fn relaxed_char_policy() {
+        let text = "tab\there, back\\slash";
+        assert!(TransactionData::new(1, 2, text).is_err());
+        let data = TransactionData::with_policy(
+            1,
+            2,
+            text,
+            DataFormat::V1,
+            CharPolicy::Relaxed,
+        ).unwrap();
+        let line = data.to_string();
+        assert_eq!(line, "01;02;tab\\there, back\\\\slash");
+        assert!(!line.contains('\t'));
+        let parsed =
+            TransactionData::parse_with_policy(&line, CharPolicy::Relaxed)
+                .unwrap();
+        assert_eq!(parsed, data);
+        assert_eq!(parsed.text(), text);
+        let bad_escape = "01;02;a\\x";
+        assert!(
+            TransactionData::parse_with_policy(bad_escape, CharPolicy::Relaxed)
+                .is_err()
+        );
+
+        for policy in &[CharPolicy::Strict, CharPolicy::Relaxed] {
+            for text in &["Te;st", "nul\0", "new\nline"] {
+                assert!(TransactionData::with_policy(
+                    1,
+                    2,
+                    text,
+                    DataFormat::V1,
+                    *policy,
+                ).is_err());
+            }
+        }
+    }
+
+    #[test]
+    fn escaped_char_policy() {
+        let text = "a;b\nc\\s\r\t\0end";
+        let data = TransactionData::with_policy(
+            1,
+            2,
+            text,
+            DataFormat::V1,
+            CharPolicy::Escaped,
+        ).unwrap();
+        let field = data.to_string();
+        assert_eq!(field, "01;02;a\\sb\\nc\\\\s\\r\\t\\0end");
+        let parsed =
+            TransactionData::parse_with_policy(&field, CharPolicy::Escaped)
+                .unwrap();
+        assert_eq!(parsed, data);
+        assert_eq!(parsed.text(), text);
+        let bad_escape = "01;02;a\\x";
+        assert!(
+            TransactionData::parse_with_policy(bad_escape, CharPolicy::Escaped)
+                .is_err()
+        );
+
+        // the line stays one record and its hash covers the escaped text
+        let tx = Transaction::new(
+            TransactionId::new(1).unwrap(),
+            "041017-10:00:00".parse().unwrap(),
+            data,
+            None,
+        );
+        let line = tx.to_string();
+        assert_eq!(line.lines().count(), 1);
+        let escaped = LogFormat {
+            policy: CharPolicy::Escaped,
+            ..LogFormat::default()
+        };
+        let read = Transaction::parse_with_format(&line, escaped).unwrap();
+        assert!(verify_transaction(&read, None).is_ok());
+        assert_eq!(read.hash().to_string(), tx.hash().to_string());
+        assert_eq!(read.data().to_string(), field);
+        assert_eq!(read.data().text(), text);
+    }
+
+    #[test]
+    fn explain_tampered_text() {
+        let tx1 = Transaction::new(
+            TransactionId::new(1).unwrap(),
+            "041017-10:00:00".parse().unwrap(),
+            TransactionData::new(1, 2, "first").unwrap(),
+            None,
+        );
+        let tx2 = Transaction::new(
+            TransactionId::new(2).unwrap(),
+            "041017-10:00:01".parse().unwrap(),
+            TransactionData::new(1, 2, "second").unwrap(),
+            Some(&tx1),
+        );
+        assert_eq!(
+            tx2.explain_mismatch(Some(&tx1)),
+            "Hash of transaction 00000002 matches"
+        );
+
+        let tampered: Transaction = tx2.to_string()
+            .replace("second", "forged")
+            .parse()
+            .unwrap();
+        let explanation = tampered.explain_mismatch(Some(&tx1));
+        assert!(explanation.starts_with("Hash mismatch in transaction"));
+        assert!(explanation.contains(&tx2.hash().to_string()));
+        assert!(explanation.contains(&format!(
+            "`00000002;{};01;02;forged;{}`",
+            tx2.ts(),
+            tx1.hash()
+        )));
+    }
+
     #[test]
     fn verify_ok() {
         let tx1 = Transaction::new(
@@ -425,6 +1732,26 @@ mod test {
         assert_eq!(verify_transaction(&tx2, Some(&tx1)), Ok(()));
     }
 
+    #[test]
+    fn verify_prev_hash() {
+        let tx1 = Transaction::new(
+            TransactionId::new(1).unwrap(),
+            "041017-10:00:00".parse::<TransactionTime>().unwrap(),
+            TransactionData::new(0, 1, "Testü").unwrap(),
+            None,
+        );
+        let tx2 = Transaction::new(
+            TransactionId::new(2).unwrap(),
+            "041017-10:00:00".parse::<TransactionTime>().unwrap(),
+            TransactionData::new(0, 1, "Großes ß").unwrap(),
+            Some(&tx1),
+        );
+        assert_eq!(verify_with_prev_hash(&tx1, None), Ok(()));
+        assert_eq!(verify_with_prev_hash(&tx2, Some(tx1.hash())), Ok(()));
+        let err = verify_with_prev_hash(&tx2, None).unwrap_err();
+        assert_eq!(err.id(), 2);
+    }
+
     #[test]
     fn verify_err() {
         let tx1 = Transaction::new(
@@ -439,7 +1766,123 @@ mod test {
             TransactionData::new(0, 1, "Großes S").unwrap(),
             None,
         );
-        assert!(verify_transaction(&tx2, Some(&tx1)).is_err());
+        let err = verify_transaction(&tx2, Some(&tx1)).unwrap_err();
+        assert_eq!(err, VerifyError::NonConsecutiveID(1, 1));
+        assert_eq!(err.id(), 1);
+    }
+
+    #[test]
+    fn verify_err_hashes() {
+        let tx = Transaction::new(
+            TransactionId::new(1).unwrap(),
+            "041017-10:00:00".parse::<TransactionTime>().unwrap(),
+            TransactionData::new(0, 1, "Testü").unwrap(),
+            None,
+        );
+        let tampered: Transaction =
+            tx.to_string().replace("Testü", "Testu").parse().unwrap();
+        let expected = TransactionHash::new(
+            tampered.id(),
+            tampered.ts(),
+            tampered.data(),
+            None,
+        );
+        match verify_transaction(&tampered, None) {
+            Err(VerifyError::MissmatchingHash { id, expected: e, actual }) => {
+                assert_eq!(id, 1);
+                assert_eq!(e, expected.to_string());
+                assert_eq!(actual, tx.hash().to_string());
+                assert_ne!(e, actual);
+                assert_eq!(e.len(), 64);
+            }
+            other => panic!("unexpected {:?}", other),
+        }
+    }
+
+    #[test]
+    fn age_by_clock() {
+        use chrono::Duration;
+        use crate::clock::{with_clock, FixedClock};
+
+        let tx = Transaction::new(
+            TransactionId::new(1).unwrap(),
+            "041017-10:00:00".parse().unwrap(),
+            TransactionData::new(1, 2, "aged").unwrap(),
+            None,
+        );
+        // 10:00:00 at the storage offset of one hour is 09:00:00 UTC
+        let at = |h, m| FixedClock(Utc.ymd(2017, 10, 4).and_hms(h, m, 0));
+        with_clock(at(9, 30), || {
+            assert_eq!(tx.age(), Duration::minutes(30));
+            assert!(tx.is_older_than(Duration::minutes(29)));
+            assert!(!tx.is_older_than(Duration::minutes(30)));
+        });
+        with_clock(at(8, 0), || {
+            assert_eq!(tx.age(), Duration::hours(-1));
+            assert!(!tx.is_older_than(Duration::zero()));
+            assert!(tx.is_older_than(Duration::hours(-2)));
+        });
+    }
+
+    #[test]
+    fn gid_counts_and_seqs() {
+        let mut txs: Vec<Transaction> = Vec::new();
+        for (i, gid) in [3, 5, 3, 3, 7, 5].iter().enumerate() {
+            let tx = Transaction::new(
+                TransactionId::new(i as u32 + 1).unwrap(),
+                "041017-10:00:00".parse().unwrap(),
+                TransactionData::new(*gid, 1, "grouped").unwrap(),
+                txs.last(),
+            );
+            txs.push(tx);
+        }
+        let counts = gid_counts(&txs);
+        assert_eq!(counts.values().sum::<usize>(), txs.len());
+        assert_eq!(counts[&Gid::new(3).unwrap()], 3);
+        assert_eq!(counts[&Gid::new(5).unwrap()], 2);
+        assert_eq!(counts[&Gid::new(7).unwrap()], 1);
+
+        let seqs: Vec<_> = txs.iter()
+            .map(|t| gid_seq(&txs, t.id()).unwrap())
+            .collect();
+        assert_eq!(seqs, vec![1, 1, 2, 3, 1, 2]);
+        assert!(gid_seq(&txs, &TransactionId::new(9).unwrap()).is_none());
+        assert!(gid_counts(&[]).is_empty());
+    }
+
+    #[test]
+    fn merkle_root_covers_every_transaction() {
+        let empty = merkle_root(Vec::new());
+        assert_eq!(
+            empty.to_string(),
+            "E3B0C44298FC1C149AFBF4C8996FB92427AE41E4649B934CA495991B7852B855"
+        );
+
+        let chain = |changed: Option<usize>| {
+            let mut txs: Vec<Transaction> = Vec::new();
+            for i in 0..5 {
+                let text = if Some(i) == changed { "changed" } else { "same" };
+                let tx = Transaction::new(
+                    TransactionId::new(i as u32 + 1).unwrap(),
+                    "041017-10:00:00".parse().unwrap(),
+                    TransactionData::new(0, 1, text).unwrap(),
+                    txs.last(),
+                );
+                txs.push(tx);
+            }
+            txs
+        };
+        let root = merkle_root(chain(None)).to_string();
+        assert_eq!(root, merkle_root(chain(None)).to_string());
+        assert_ne!(root, empty.to_string());
+        for i in 0..5 {
+            let changed = merkle_root(chain(Some(i))).to_string();
+            assert_ne!(root, changed, "changed {}", i);
+        }
+        // a single leaf is still hashed into the root
+        let first = chain(None).remove(0);
+        let root = merkle_root(vec![first.clone()]);
+        assert_ne!(root.as_slice(), first.hash().as_slice());
     }
 
 
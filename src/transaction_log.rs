@@ -1,11 +1,18 @@
-use std::collections::BTreeMap;
+use std::collections::{BTreeMap, BTreeSet, HashMap};
 use std::fmt;
-use std::fs::{File, OpenOptions};
+use std::fs::{self, File, OpenOptions};
 use std::io;
 use std::io::prelude::*;
-use std::path::Path;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
 
-use transaction::*;
+#[cfg(feature = "flate2")]
+use flate2::read::GzDecoder;
+
+use crate::broadcast::Broadcast;
+use crate::hex;
+use crate::id_allocator::{IdAllocator, SequentialAllocator};
+use crate::transaction::*;
 
 
 pub trait TransactionLog {
@@ -18,30 +25,160 @@ pub trait TransactionLog {
 
     fn last(&self) -> Result<Option<Transaction>, Self::Error>;
 
+    /// Format the log writes transactions in. Times given to `create` are
+    /// converted to it, file logs also convert the data to its policy.
+    fn format(&self) -> Result<LogFormat, Self::Error> {
+        Ok(LogFormat::default())
+    }
+
     fn next_id(&self) -> Result<TransactionId, Self::Error> {
-        Ok(self.last()?.map(|t| t.id().next()).unwrap_or_default())
+        self.next_id_after(self.last()?.as_ref())
+    }
+
+    /// Id of a transaction following `last`, consecutive by default.
+    fn next_id_after(
+        &self,
+        last: Option<&Transaction>,
+    ) -> Result<TransactionId, Self::Error> {
+        Ok(last.map(|t| t.id().next()).unwrap_or_default())
+    }
+
+    fn last_hash(&self) -> Result<Option<TransactionHash>, Self::Error> {
+        Ok(self.last()?.map(|t| t.hash().clone()))
+    }
+
+    /// Stores transactions which are already chained to `last()`.
+    fn append(&mut self, txs: &[Transaction]) -> Result<(), Self::Error>;
+
+    /// The transaction `create` would make right now, without storing it
+    /// or using up its id.
+    fn preview(
+        &self,
+        data: TransactionData,
+        time: Option<TransactionTime>,
+    ) -> Result<Transaction, Self::Error> {
+        Ok(Transaction::with_prev_hash(
+            self.next_id()?,
+            self.format()?.stamp(time),
+            data,
+            self.last_hash()?.as_ref(),
+        ))
+    }
+
+    /// Creates all `items` in order, like calling `create` for each, but
+    /// reads `last()` only once and chains the transactions in memory
+    /// before storing them with a single `append`.
+    fn create_batch(
+        &mut self,
+        items: Vec<(TransactionData, Option<TransactionTime>)>,
+    ) -> Result<Vec<Transaction>, Self::Error> {
+        let mut last = self.last()?;
+        let format = self.format()?;
+        let mut txs = Vec::with_capacity(items.len());
+        for (data, time) in items {
+            let tx = Transaction::with_prev_hash(
+                self.next_id_after(last.as_ref())?,
+                format.stamp(time),
+                data,
+                last.as_ref().map(|t| t.hash()),
+            );
+            last = Some(tx.clone());
+            txs.push(tx);
+        }
+        if !txs.is_empty() {
+            self.append(&txs)?;
+        }
+        Ok(txs)
     }
 }
 
 pub trait GetById {
     type Error: fmt::Debug;
     fn get_by_id(&self, id: u32) -> Result<Option<Transaction>, Self::Error>;
+
+    /// Whether the transaction `id` exists, without necessarily building it.
+    fn contains(&self, id: u32) -> Result<bool, Self::Error> {
+        Ok(self.get_by_id(id)?.is_some())
+    }
 }
 
 pub trait GetAll {
     type Error: fmt::Debug;
     fn get_all(&self) -> Result<Vec<Transaction>, Self::Error>;
+
+    /// The oldest transaction, the genesis of the chain.
+    fn first(&self) -> Result<Option<Transaction>, Self::Error> {
+        Ok(self.get_all()?.into_iter().next())
+    }
+
+    /// The transactions following the one with `hash`, or `None` if no
+    /// transaction has that hash.
+    fn after_hash(
+        &self,
+        hash: &TransactionHash,
+    ) -> Result<Option<Vec<Transaction>>, Self::Error> {
+        let mut all = self.get_all()?;
+        let pos = all.iter()
+            .position(|t| t.hash().as_slice() == hash.as_slice());
+        Ok(pos.map(|i| all.split_off(i + 1)))
+    }
+
+    /// The transaction with `hash`. Scans the whole log, logs able to look
+    /// hashes up directly should override this.
+    fn get_by_hash(
+        &self,
+        hash: &TransactionHash,
+    ) -> Result<Option<Transaction>, Self::Error> {
+        Ok(self.get_all()?
+            .into_iter()
+            .find(|t| t.hash().as_slice() == hash.as_slice()))
+    }
+}
+
+pub trait GetTail {
+    type Error: fmt::Debug;
+    /// The last `n` transactions, oldest first.
+    fn tail(&self, n: usize) -> Result<Vec<Transaction>, Self::Error>;
 }
 
 
+/// In-memory log. Besides the transactions it indexes their ids by
+/// uppercase hash, which costs about 100 bytes per transaction on top of
+/// the transaction itself.
 #[derive(Debug)]
 pub struct FullTransactionLog {
     log: BTreeMap<u32, Transaction>,
+    by_hash: HashMap<String, u32>,
+}
+
+/// Key of `hash` in the hash index, independent of how it was parsed.
+fn hash_key(hash: &TransactionHash) -> String {
+    hex::encode_upper(hash.as_slice())
 }
 
 impl FullTransactionLog {
     pub fn new() -> Self {
-        FullTransactionLog { log: BTreeMap::new() }
+        FullTransactionLog {
+            log: BTreeMap::new(),
+            by_hash: HashMap::new(),
+        }
+    }
+
+    fn insert(&mut self, tx: Transaction) {
+        let id = tx.id().inner();
+        let key = hash_key(tx.hash());
+        if let Some(old) = self.log.insert(id, tx) {
+            self.by_hash.remove(&hash_key(old.hash()));
+        }
+        self.by_hash.insert(key, id);
+    }
+
+    fn remove(&mut self, id: u32) -> Option<Transaction> {
+        let tx = self.log.remove(&id);
+        if let Some(ref tx) = tx {
+            self.by_hash.remove(&hash_key(tx.hash()));
+        }
+        tx
     }
 }
 
@@ -54,21 +191,31 @@ impl TransactionLog for FullTransactionLog {
         time: Option<TransactionTime>,
     ) -> Result<Transaction, Self::Error> {
         let next_id = self.next_id()?;
-        let last = self.last()?;
-        let tx = Transaction::new(
+        let last_hash = self.last_hash()?;
+        let tx = Transaction::with_prev_hash(
             next_id,
-            time.unwrap_or_else(|| TransactionTime::current()),
+            self.format()?.stamp(time),
             data,
-            last.as_ref(),
+            last_hash.as_ref(),
         );
-        let c = tx.clone();
-        self.log.insert(tx.id().inner(), tx);
-        Ok(c)
+        self.insert(tx.clone());
+        Ok(tx)
     }
 
     fn last(&self) -> Result<Option<Transaction>, Self::Error> {
         Ok(self.log.iter().next_back().map(|(_, t)| t).cloned())
     }
+
+    fn last_hash(&self) -> Result<Option<TransactionHash>, Self::Error> {
+        Ok(self.log.values().next_back().map(|t| t.hash().clone()))
+    }
+
+    fn append(&mut self, txs: &[Transaction]) -> Result<(), Self::Error> {
+        for tx in txs {
+            self.insert(tx.clone());
+        }
+        Ok(())
+    }
 }
 
 impl GetById for FullTransactionLog {
@@ -76,6 +223,10 @@ impl GetById for FullTransactionLog {
     fn get_by_id(&self, id: u32) -> Result<Option<Transaction>, Self::Error> {
         Ok(self.log.get(&id).cloned())
     }
+
+    fn contains(&self, id: u32) -> Result<bool, Self::Error> {
+        Ok(self.log.contains_key(&id))
+    }
 }
 
 impl GetAll for FullTransactionLog {
@@ -83,6 +234,30 @@ impl GetAll for FullTransactionLog {
     fn get_all(&self) -> Result<Vec<Transaction>, Self::Error> {
         Ok(self.log.values().cloned().collect())
     }
+
+    fn first(&self) -> Result<Option<Transaction>, Self::Error> {
+        Ok(self.log.values().next().cloned())
+    }
+
+    fn get_by_hash(
+        &self,
+        hash: &TransactionHash,
+    ) -> Result<Option<Transaction>, Self::Error> {
+        Ok(self.by_hash
+            .get(&hash_key(hash))
+            .and_then(|id| self.log.get(id))
+            .cloned())
+    }
+}
+
+impl GetTail for FullTransactionLog {
+    type Error = ();
+    fn tail(&self, n: usize) -> Result<Vec<Transaction>, Self::Error> {
+        let mut tail: Vec<_> =
+            self.log.values().rev().take(n).cloned().collect();
+        tail.reverse();
+        Ok(tail)
+    }
 }
 
 
@@ -107,134 +282,622 @@ quick_error! {
     }
 }
 
+/// Message of the `FileError::Other` returned once a `DualLog` reached one of
+/// its limits.
+pub const LOG_FULL: &'static str = "log full";
+
+/// Message of the `FileError::Other` returned when pruning would not leave a
+/// chain, see `SimpleFileLog::prune_older_than`.
+pub const DANGLING_CHAIN: &'static str = "pruning would leave a dangling chain";
+
+/// Line reader over a plain or gzip compressed log file, or over nothing
+/// for a missing file.
+enum LogReader {
+    Plain(io::BufReader<File>),
+    #[cfg(feature = "flate2")]
+    Gzip(io::BufReader<GzDecoder<File>>),
+    Missing(io::Empty),
+}
+
+impl LogReader {
+    fn records(self) -> Records {
+        Records(self.lines())
+    }
+}
+
+/// Lines of a log file with `\n` or `\r\n` line endings, so files edited on
+/// Windows still parse and verify. Lines are always written with `\n`.
+/// Header lines starting with `HEADER_PREFIX` are skipped.
+struct Records(io::Lines<LogReader>);
+
+/// Start of lines holding metadata instead of a transaction.
+const HEADER_PREFIX: &'static str = "#";
+
+/// Start of the header line recording the `LogFormat` of a file, which
+/// comes first if there is one.
+const FORMAT_HEADER: &'static str = "# format ";
+
+/// The header line recording `format`, left out for the default format so
+/// such files stay readable by older versions.
+fn format_header(format: LogFormat) -> String {
+    if format == LogFormat::default() {
+        return String::new();
+    }
+    format!("{}{}\n", FORMAT_HEADER, format)
+}
+
+/// Start of the header line keeping the first transaction of a pruned
+/// file, see `SimpleFileLog::pruned_genesis`.
+const GENESIS_HEADER: &'static str = "# genesis ";
+
+/// The header line keeping `genesis`, if there is one.
+fn genesis_header(genesis: Option<&Transaction>) -> String {
+    match genesis {
+        Some(tx) => format!("{}{}\n", GENESIS_HEADER, tx),
+        None => String::new(),
+    }
+}
+
+impl Iterator for Records {
+    type Item = io::Result<String>;
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            match self.0.next() {
+                Some(Ok(ref l)) if l.starts_with(HEADER_PREFIX) => continue,
+                line => {
+                    return line.map(|line| {
+                        line.map(|mut l| {
+                            if l.ends_with('\r') {
+                                l.pop();
+                            }
+                            l
+                        })
+                    })
+                }
+            }
+        }
+    }
+}
+
+impl Read for LogReader {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        match *self {
+            LogReader::Plain(ref mut r) => r.read(buf),
+            #[cfg(feature = "flate2")]
+            LogReader::Gzip(ref mut r) => r.read(buf),
+            LogReader::Missing(ref mut r) => r.read(buf),
+        }
+    }
+}
+
+impl BufRead for LogReader {
+    fn fill_buf(&mut self) -> io::Result<&[u8]> {
+        match *self {
+            LogReader::Plain(ref mut r) => r.fill_buf(),
+            #[cfg(feature = "flate2")]
+            LogReader::Gzip(ref mut r) => r.fill_buf(),
+            LogReader::Missing(ref mut r) => r.fill_buf(),
+        }
+    }
+
+    fn consume(&mut self, amt: usize) {
+        match *self {
+            LogReader::Plain(ref mut r) => r.consume(amt),
+            #[cfg(feature = "flate2")]
+            LogReader::Gzip(ref mut r) => r.consume(amt),
+            LogReader::Missing(ref mut r) => r.consume(amt),
+        }
+    }
+}
+
+/// Transaction log stored as one line per transaction. Paths ending in `.gz`
+/// are read as gzip compressed archives (with the `flate2` feature) and
+/// cannot be appended to. A missing file reads as an empty log and is
+/// created again by the next append.
 #[derive(Debug)]
 pub struct SimpleFileLog<P: AsRef<Path>> {
     path: P,
+    chunk_size: u64,
+    max_tail: u64,
+    read_only: bool,
+    verify_on_read: bool,
+    verify_window: Option<usize>,
+    read_back: bool,
+    allocator: Box<dyn IdAllocator>,
+    format: LogFormat,
 }
 
 impl<P: AsRef<Path>> SimpleFileLog<P> {
+    pub const DEFAULT_CHUNK_SIZE: u64 = 8_192;
+    pub const DEFAULT_MAX_TAIL: u64 = 16 * 1024 * 1024;
+    /// Length of a line with a short text, used to estimate the number of
+    /// transactions from the file size.
+    pub const TYPICAL_LINE_LEN: u64 = 128;
+
     pub fn new(path: P) -> Self {
         SimpleFileLog {
-            path
+            path,
+            chunk_size: Self::DEFAULT_CHUNK_SIZE,
+            max_tail: Self::DEFAULT_MAX_TAIL,
+            read_only: false,
+            verify_on_read: true,
+            verify_window: None,
+            read_back: cfg!(debug_assertions),
+            allocator: Box::new(SequentialAllocator),
+            format: LogFormat::default(),
         }
     }
-}
 
-impl<P: AsRef<Path>> TransactionLog for SimpleFileLog<P>{
-    type Error = FileError;
+    /// Opens a log which can be read, but refuses to create transactions,
+    /// e.g. for a replica reading a shared file.
+    pub fn read_only(path: P) -> Self {
+        SimpleFileLog {
+            read_only: true,
+            ..Self::new(path)
+        }
+    }
 
-    fn create(
-        &mut self,
-        data: TransactionData,
-        time: Option<TransactionTime>,
-    ) -> Result<Transaction, Self::Error> {
-        let tx = Transaction::new(
-            self.next_id()?,
-            time.unwrap_or_else(|| TransactionTime::current()),
-            data,
-            self.last()?.as_ref(),
-        );
+    /// Sets the number of bytes first read from the end of the file when
+    /// looking for the last transactions. The window doubles until enough
+    /// complete lines are found, so small values stay correct.
+    pub fn with_chunk_size(mut self, chunk_size: u64) -> Self {
+        self.chunk_size = if chunk_size == 0 { 1 } else { chunk_size };
+        self
+    }
+
+    /// Sets the largest window read from the end of the file. A file without
+    /// enough line breaks in it is considered corrupt instead of being read
+    /// completely.
+    pub fn with_max_tail(mut self, max_tail: u64) -> Self {
+        self.max_tail = max_tail;
+        self
+    }
+
+    /// Whether `get_all` verifies the chain, on by default. Logs which are
+    /// trusted and read often can turn it off and verify the chain
+    /// periodically instead. Appending still verifies against the file.
+    pub fn with_verify_on_read(mut self, verify_on_read: bool) -> Self {
+        self.verify_on_read = verify_on_read;
+        self
+    }
+
+    /// Makes `get_all` verify only the links of the last `links`
+    /// transactions to their predecessors, instead of the whole chain.
+    /// Earlier links are trusted to have been verified before, so a record
+    /// changed before the window goes unnoticed until `verify_chain` runs.
+    pub fn with_verify_window(mut self, links: usize) -> Self {
+        self.verify_window = Some(links);
+        self
+    }
+
+    /// Whether `create` reads the line it wrote back and fails unless it
+    /// parses to the created transaction, to catch corrupted writes early.
+    /// On in debug builds.
+    pub fn with_read_back(mut self, read_back: bool) -> Self {
+        self.read_back = read_back;
+        self
+    }
+
+    /// Format of a new file, and of a file without a format header, which
+    /// older versions wrote. Files with a header are read and continued in
+    /// the format it records, see `TransactionLog::format`.
+    pub fn with_format(mut self, format: LogFormat) -> Self {
+        self.format = format;
+        self
+    }
+
+    /// Fails unless the last line of the file is `tx`.
+    fn check_written(&self, tx: &Transaction) -> Result<(), FileError> {
+        let line = self.tail_lines(1)?.pop().unwrap_or_default();
+        // compared as text, the file keeps timestamps to the second only
+        let same = Transaction::parse_with_format(&line, self.format()?)
+            .map(|written| written.to_string() == tx.to_string())
+            .unwrap_or(false);
+        if !same {
+            return Err(FileError::Other(format!(
+                "Transaction {} was written as `{}`",
+                tx.id(),
+                line
+            )));
+        }
+        Ok(())
+    }
+
+    /// Verifies every link of the chain, regardless of the verify window.
+    pub fn verify_chain(&self) -> Result<(), FileError> {
+        let format = self.format()?;
+        let mut last_tx: Option<Transaction> = None;
+        for l in self.reader()?.records() {
+            let tx = Transaction::parse_with_format(&l?, format)?;
+            self.verify(&tx, last_tx.as_ref())?;
+            last_tx = Some(tx);
+        }
+        Ok(())
+    }
+
+    /// Allocates ids with `allocator` instead of consecutively. Reading
+    /// verifies that each id follows the previous one by `allocator`.
+    pub fn with_allocator(mut self, allocator: Box<dyn IdAllocator>) -> Self {
+        self.allocator = allocator;
+        self
+    }
+
+    /// Checks the hash of `tx` and that its id follows `prev`.
+    fn verify(
+        &self,
+        tx: &Transaction,
+        prev: Option<&Transaction>,
+    ) -> Result<(), VerifyError> {
+        if let Some(prev) = prev {
+            if !self.allocator.follows(*prev.id(), *tx.id()) {
+                return Err(VerifyError::NonConsecutiveID(
+                    prev.id().inner(),
+                    tx.id().inner(),
+                ));
+            }
+        }
+        verify_with_prev_hash(tx, prev.map(|p| p.hash()))
+    }
+
+    fn check_writable(&self) -> Result<(), FileError> {
+        if self.read_only {
+            return Err(FileError::Other("read-only log".to_owned()));
+        }
+        if self.is_gzip() {
+            return Err(FileError::Other(
+                "Appending to a gzip compressed log is not supported"
+                    .to_owned(),
+            ));
+        }
+        Ok(())
+    }
+
+    /// Appends the lines of `txs` with a single write, without checking
+    /// that they chain onto the file. An empty file first gets the header
+    /// of `format`.
+    fn write_lines(
+        &self,
+        txs: &[Transaction],
+        format: LogFormat,
+    ) -> Result<(), FileError> {
+        for tx in txs {
+            if tx.ts().format() != format.time
+                || tx.data().policy() != format.policy
+            {
+                return Err(FileError::Other(format!(
+                    "Transaction {} is not written in the format `{}` \
+                     of the log",
+                    tx.id(),
+                    format
+                )));
+            }
+        }
+        let mut lines = String::new();
+        if self.disk_bytes()? == 0 {
+            lines.push_str(&format_header(format));
+        }
+        lines.extend(txs.iter().map(|tx| format!("{}\n", tx)));
+        if self.is_missing() {
+            eprintln!(
+                "Warning: log file {:?} is missing, starting it anew",
+                self.path.as_ref()
+            );
+        }
         let mut f = OpenOptions::new()
             .write(true)
             .create(true)
             .open(self.path.as_ref())?;
         f.seek(io::SeekFrom::End(0))?;
-        f.write_all(format!("{}\n", tx).as_bytes())?;
-        Ok(tx)
+        f.write_all(lines.as_bytes())?;
+        Ok(())
     }
 
-    fn last(&self) -> Result<Option<Transaction>, Self::Error> {
-        let mut f = File::open(self.path.as_ref())?;
-        let mut buffer = String::new();
-        let file_size = f.metadata()?.len();
-        let chunk_size = 10_240;
-        let start_pos = if file_size < chunk_size { 0 } else { file_size - chunk_size };
-        f.seek(io::SeekFrom::Start(start_pos))?;
-        f.take(chunk_size).read_to_string(&mut buffer)?;
-
-        //FIXME: use proper error handling to make mapping:
-        // None -> None
-        // Some(Ok(t)) -> Some(t)
-        // Some(Err(e)) -> early return Err(e)
-        let mut lines = buffer.lines().rev();
-
-        let last_tx = match lines.next()
-            .map(|line| line.parse::<Transaction>()) {
-            Some(Ok(t)) => Some(t),
-            Some(Err(e)) => return Err(e.into()),
-            None => None,
-        };
+    /// Drops every line which doesn't parse or doesn't verify after the last
+    /// kept transaction, logging each with its line number. If any line is
+    /// dropped, the original file is kept as `<path>.corrupt` and the file
+    /// is rewritten with its header lines and the remaining chain. Returns
+    /// the number of dropped lines.
+    pub fn recover(&mut self) -> Result<usize, FileError> {
+        let format = self.format()?;
+        let mut headers = String::new();
+        let mut kept: Vec<Transaction> = Vec::new();
+        let mut dropped = 0;
+        // numbers the raw lines, header lines included
+        for (i, l) in self.reader()?.lines().enumerate() {
+            let mut line = l?;
+            if line.ends_with('\r') {
+                line.pop();
+            }
+            if line.starts_with(HEADER_PREFIX) {
+                headers.push_str(&line);
+                headers.push('\n');
+                continue;
+            }
+            let checked = Transaction::parse_with_format(&line, format)
+                .map_err(FileError::from)
+                .and_then(|tx| {
+                    self.verify(&tx, kept.last())?;
+                    Ok(tx)
+                });
+            match checked {
+                Ok(tx) => kept.push(tx),
+                Err(e) => {
+                    eprintln!("Dropping line {}: {:?}", i + 1, e);
+                    dropped += 1;
+                }
+            }
+        }
+        if dropped == 0 {
+            return Ok(0);
+        }
+        self.check_writable()?;
+        let backup = self.sibling(".corrupt");
+        fs::copy(self.path.as_ref(), &backup)?;
+        File::create(self.path.as_ref())?.write_all(headers.as_bytes())?;
+        self.append(&kept)?;
+        eprintln!(
+            "Dropped {} lines, kept {} transactions, original saved as {:?}",
+            dropped,
+            kept.len(),
+            backup
+        );
+        Ok(dropped)
+    }
 
-        let last2_tx = match lines.next()
-            .map(|line| line.parse::<Transaction>()) {
-            Some(Ok(t)) => Some(t),
-            Some(Err(e)) => return Err(e.into()),
-            None => None,
+    /// Writes the log to `output` with every hash recomputed from the first
+    /// transaction on, keeping ids, times and data. Returns the number of
+    /// hashes which changed. The whole log is read before writing, so
+    /// `output` may be the log itself.
+    pub fn rebuild<Q: AsRef<Path>>(
+        &self,
+        output: Q,
+    ) -> Result<usize, FileError> {
+        let format = self.format()?;
+        let mut rebuilt: Vec<Transaction> = Vec::new();
+        let mut changed = 0;
+        for l in self.reader()?.records() {
+            let tx = Transaction::parse_with_format(&l?, format)?;
+            let fixed = Transaction::new(
+                *tx.id(),
+                tx.ts().clone(),
+                tx.data().clone(),
+                rebuilt.last(),
+            );
+            if fixed.hash().as_slice() != tx.hash().as_slice() {
+                changed += 1;
+            }
+            rebuilt.push(fixed);
+        }
+        File::create(output.as_ref())?;
+        SimpleFileLog::new(output).with_format(format).append(&rebuilt)?;
+        Ok(changed)
+    }
+
+    /// Removes the transactions older than `cutoff` from the start of the
+    /// log. The first remaining transaction is re-anchored as if it had no
+    /// predecessor, which recomputes every remaining hash, and a header line
+    /// records the hash the chain used to continue from. The first
+    /// transaction of the log is kept in a header, see `pruned_genesis`.
+    /// The pruned file is written next to the log and renamed over it, with
+    /// the original kept as `<path>.pruned`. Fails with
+    /// `DANGLING_CHAIN` if an old transaction follows a newer one, or if no
+    /// transaction would remain to continue the ids from. Returns the
+    /// number of pruned transactions.
+    pub fn prune_older_than(
+        &mut self,
+        cutoff: &TransactionTime,
+    ) -> Result<usize, FileError> {
+        self.check_writable()?;
+        let all = self.get_all()?;
+        let pruned = all.iter().take_while(|t| t.ts() < cutoff).count();
+        if pruned == 0 {
+            return Ok(0);
+        }
+        if pruned == all.len() || all[pruned..].iter().any(|t| t.ts() < cutoff)
+        {
+            return Err(FileError::Other(DANGLING_CHAIN.to_owned()));
+        }
+        let mut kept: Vec<Transaction> = Vec::with_capacity(all.len() - pruned);
+        for tx in &all[pruned..] {
+            let anchored = Transaction::new(
+                *tx.id(),
+                tx.ts().clone(),
+                tx.data().clone(),
+                kept.last(),
+            );
+            kept.push(anchored);
+        }
+        let genesis = match all.first() {
+            Some(tx) if tx.id().inner() == TransactionId::MIN_ID => {
+                Some(tx.clone())
+            }
+            _ => self.pruned_genesis()?,
         };
+        let mut lines = format!(
+            "{}{}{} pruned up to {}, which hashed to {}\n",
+            format_header(self.format()?),
+            genesis_header(genesis.as_ref()),
+            HEADER_PREFIX,
+            all[pruned - 1].id(),
+            all[pruned - 1].hash()
+        );
+        lines.extend(kept.iter().map(|tx| format!("{}\n", tx)));
+        // the log is only replaced once the pruned file is complete
+        let tmp = self.sibling(".tmp");
+        let mut f = File::create(&tmp)?;
+        f.write_all(lines.as_bytes())?;
+        f.sync_all()?;
+        fs::copy(self.path.as_ref(), self.sibling(".pruned"))?;
+        fs::rename(&tmp, self.path.as_ref())?;
+        Ok(pruned)
+    }
 
-        match (&last2_tx, &last_tx) {
-            (&Some(ref tx1), &Some(ref tx2)) => {
-                verify_transaction(tx2, Some(tx1))?;
-            },
-            (&None, &Some(ref tx2)) => {
-                verify_transaction(tx2, None)?;
-            },
-            (&None, &None) => {
-                // no verify necessary
-            },
-            _ => panic!("Unexpected file behavior, last tx does not exist, but second last")
+    /// The path of the log with `suffix` appended.
+    fn sibling(&self, suffix: &str) -> PathBuf {
+        let mut path = self.path.as_ref().as_os_str().to_owned();
+        path.push(suffix);
+        PathBuf::from(path)
+    }
+
+    /// The first transaction of the log if it was pruned, so
+    /// `DualLog::ensure_genesis` can still check it.
+    pub fn pruned_genesis(&self) -> Result<Option<Transaction>, FileError> {
+        let format = self.format()?;
+        match self.header(GENESIS_HEADER)? {
+            Some(line) => {
+                Ok(Some(Transaction::parse_with_format(&line, format)?))
+            }
+            None => Ok(None),
         }
-        Ok(last_tx)
     }
 
+    /// The rest of the leading header line starting with `prefix`.
+    fn header(&self, prefix: &str) -> Result<Option<String>, FileError> {
+        for line in self.reader()?.lines() {
+            let line = line?;
+            if !line.starts_with(HEADER_PREFIX) {
+                break;
+            }
+            if let Some(rest) = line.strip_prefix(prefix) {
+                return Ok(Some(rest.trim_end().to_owned()));
+            }
+        }
+        Ok(None)
+    }
 
-}
+    /// Size of the log file in bytes, 0 if it is missing.
+    pub fn disk_bytes(&self) -> Result<u64, FileError> {
+        match self.path.as_ref().metadata() {
+            Ok(meta) => Ok(meta.len()),
+            Err(ref e) if e.kind() == io::ErrorKind::NotFound => Ok(0),
+            Err(e) => Err(e.into()),
+        }
+    }
 
-impl<P: AsRef<Path>> GetAll for SimpleFileLog<P> {
-    type Error = FileError;
-    fn get_all(&self) -> Result<Vec<Transaction>, Self::Error> {
-        let f = File::open(self.path.as_ref())?;
-        let mut lines = io::BufReader::new(f).lines();
-        let mut vec = Vec::with_capacity(lines.size_hint().0);
-        {
-            let mut last_tx = None;
-            for l in lines {
-                let line = l?;
-                let tx: Transaction = line.parse()?;
-                verify_transaction(&tx, last_tx.as_ref())?;
-                vec.push(tx.clone());
-                last_tx = Some(tx);
+    /// The bytes of the log file as stored, without parsing them. Only the
+    /// current length is read, so lines appended meanwhile are left out.
+    /// Empty if the file is missing.
+    pub fn export(&self) -> Result<Box<dyn Read + Send>, FileError> {
+        match self.open()? {
+            Some(file) => {
+                let len = file.metadata()?.len();
+                Ok(Box::new(file.take(len)))
             }
+            None => Ok(Box::new(io::empty())),
         }
-        Ok(vec)
     }
-}
 
-#[derive(Debug)]
-pub struct DualLog<P: AsRef<Path>> {
-    full_log: FullTransactionLog,
-    file_log: SimpleFileLog<P>,
-}
+    /// Whether the log file does not exist, e.g. because it was deleted
+    /// while the log was in use.
+    pub fn is_missing(&self) -> bool {
+        match self.path.as_ref().metadata() {
+            Err(ref e) => e.kind() == io::ErrorKind::NotFound,
+            Ok(_) => false,
+        }
+    }
 
-impl<P: AsRef<Path>> DualLog<P> {
-    pub fn load(path: P) -> Result<Self, FileError> {
-        let file_log = SimpleFileLog::new(path);
-        let all = file_log.get_all()?;
-        let map: BTreeMap<u32, Transaction> = all
-            .into_iter()
-            .map(|t| (t.id().inner(), t))
-            .collect();
-        let full_log = FullTransactionLog {
-            log: map
+    /// Opens the log file, or `None` if it is missing.
+    fn open(&self) -> Result<Option<File>, FileError> {
+        match File::open(self.path.as_ref()) {
+            Ok(f) => Ok(Some(f)),
+            Err(ref e) if e.kind() == io::ErrorKind::NotFound => Ok(None),
+            Err(e) => Err(e.into()),
+        }
+    }
+
+    /// Rough number of transactions in the file, to reserve memory before
+    /// reading them. Too low for compressed files and long texts.
+    pub fn estimated_len(&self) -> usize {
+        self.disk_bytes()
+            .map(|bytes| (bytes / Self::TYPICAL_LINE_LEN) as usize)
+            .unwrap_or(0)
+    }
+
+    fn is_gzip(&self) -> bool {
+        self.path.as_ref().extension().map_or(false, |ext| ext == "gz")
+    }
+
+    fn reader(&self) -> Result<LogReader, FileError> {
+        let f = match self.open()? {
+            Some(f) => f,
+            None => return Ok(LogReader::Missing(io::empty())),
         };
-        Ok(DualLog {
-            full_log,
-            file_log
-        })
+        if !self.is_gzip() {
+            return Ok(LogReader::Plain(io::BufReader::new(f)));
+        }
+        #[cfg(feature = "flate2")]
+        return Ok(LogReader::Gzip(io::BufReader::new(GzDecoder::new(f))));
+        #[cfg(not(feature = "flate2"))]
+        return Err(FileError::Other(
+            "Reading gzip compressed logs requires the flate2 feature"
+                .to_owned(),
+        ));
+    }
+
+    /// Reads up to `n` complete lines from the end of the file, oldest first.
+    fn tail_lines(&self, n: usize) -> Result<Vec<String>, FileError> {
+        if self.is_gzip() {
+            // compressed files can't be read backwards, so stream through
+            let mut lines = Vec::new();
+            for l in self.reader()?.records() {
+                lines.push(l?);
+                if lines.len() > n {
+                    lines.remove(0);
+                }
+            }
+            return Ok(lines);
+        }
+        let mut f = match self.open()? {
+            Some(f) => f,
+            None => return Ok(Vec::new()),
+        };
+        let file_size = f.metadata()?.len();
+        let mut window = self.chunk_size;
+        loop {
+            let start_pos = file_size.saturating_sub(window);
+            f.seek(io::SeekFrom::Start(start_pos))?;
+            let mut buffer = Vec::new();
+            (&f).take(file_size - start_pos).read_to_end(&mut buffer)?;
+
+            let mut lines: Vec<&[u8]> = buffer.split(|b| *b == b'\n').collect();
+            if lines.last().map_or(false, |l| l.is_empty()) {
+                lines.pop();
+            }
+            // the first line is possibly cut off unless we read from the start
+            if start_pos > 0 && !lines.is_empty() {
+                lines.remove(0);
+            }
+            lines.retain(|l| !l.starts_with(HEADER_PREFIX.as_bytes()));
+
+            if lines.len() < n && start_pos > 0 && window >= self.max_tail {
+                return Err(FileError::Other(format!(
+                    "Found only {} complete lines in the last {} bytes, \
+                     the log may be corrupt",
+                    lines.len(),
+                    window
+                )));
+            }
+            if lines.len() >= n || start_pos == 0 {
+                let skip = lines.len().saturating_sub(n);
+                return lines[skip..]
+                    .iter()
+                    .map(|l| match l.last() {
+                        Some(&b'\r') => &l[..l.len() - 1],
+                        _ => l,
+                    })
+                    .map(|l| {
+                        String::from_utf8(l.to_vec()).map_err(|_| {
+                            FileError::Other("Invalid UTF-8 in log".to_owned())
+                        })
+                    })
+                    .collect();
+            }
+            window = window.saturating_mul(2).min(self.max_tail.max(1));
+        }
     }
 }
 
-impl<P: AsRef<Path>> TransactionLog for DualLog<P> {
+impl<P: AsRef<Path>> TransactionLog for SimpleFileLog<P>{
     type Error = FileError;
 
     fn create(
@@ -242,26 +905,2012 @@ impl<P: AsRef<Path>> TransactionLog for DualLog<P> {
         data: TransactionData,
         time: Option<TransactionTime>,
     ) -> Result<Transaction, Self::Error> {
-        let tx = self.file_log.create(data, time)?;
-        self.full_log.log.insert(tx.id().inner(), tx.clone());
+        self.check_writable()?;
+        // the tail is read once, so it cannot change between building the
+        // transaction and writing it
+        let last = self.last()?;
+        let format = self.format()?;
+        let tx = Transaction::new(
+            self.next_id_after(last.as_ref())?,
+            format.stamp(time),
+            data.in_policy(format.policy)?,
+            last.as_ref(),
+        );
+        self.write_lines(std::slice::from_ref(&tx), format)?;
+        if self.read_back {
+            self.check_written(&tx)?;
+        }
         Ok(tx)
     }
 
+    fn next_id_after(
+        &self,
+        last: Option<&Transaction>,
+    ) -> Result<TransactionId, Self::Error> {
+        Ok(self.allocator.next(last.map(|t| *t.id()))?)
+    }
+
+    /// Writes all lines with a single write to the file. Right before, the
+    /// first transaction is checked to still chain onto the last line, in
+    /// case the file changed since the transactions were built.
+    fn append(&mut self, txs: &[Transaction]) -> Result<(), Self::Error> {
+        self.check_writable()?;
+        if let Some(first) = txs.first() {
+            self.verify(first, self.last()?.as_ref())?;
+        }
+        self.write_lines(txs, self.format()?)
+    }
+
+    /// The format recorded in the header of the file, or the one set with
+    /// `with_format` if there is none.
+    fn format(&self) -> Result<LogFormat, Self::Error> {
+        match self.header(FORMAT_HEADER)? {
+            Some(format) => Ok(format.parse()?),
+            None => Ok(self.format),
+        }
+    }
+
     fn last(&self) -> Result<Option<Transaction>, Self::Error> {
-        Ok(self.full_log.last()?)
+        let format = self.format()?;
+        let mut txs = self.tail_lines(2)?
+            .iter()
+            .map(|line| Transaction::parse_with_format(line, format))
+            .collect::<Result<Vec<_>, _>>()?;
+        let last_tx = txs.pop();
+        let last2_tx = txs.pop();
+        if let Some(ref tx) = last_tx {
+            self.verify(tx, last2_tx.as_ref())?;
+        }
+        Ok(last_tx)
+    }
+
+    /// Only parses the hash field of the last line, without verifying it.
+    fn last_hash(&self) -> Result<Option<TransactionHash>, Self::Error> {
+        match self.tail_lines(1)?.pop() {
+            Some(line) => {
+                let hash = line.rsplit(';').next().unwrap_or_default();
+                Ok(Some(hash.parse()?))
+            }
+            None => Ok(None),
+        }
     }
 }
 
-impl<P: AsRef<Path>> GetById for DualLog<P> {
+impl<P: AsRef<Path>> GetById for SimpleFileLog<P> {
     type Error = FileError;
     fn get_by_id(&self, id: u32) -> Result<Option<Transaction>, Self::Error> {
-        Ok(self.full_log.get_by_id(id)?)
+        let format = self.format()?;
+        for l in self.reader()?.records() {
+            let tx = Transaction::parse_with_format(&l?, format)?;
+            if tx.id().inner() == id {
+                return Ok(Some(tx));
+            }
+        }
+        Ok(None)
+    }
+
+    /// Only parses the id field of each line, up to the first match.
+    fn contains(&self, id: u32) -> Result<bool, Self::Error> {
+        for l in self.reader()?.records() {
+            let line = l?;
+            let line_id: TransactionId =
+                line.split(';').next().unwrap_or_default().parse()?;
+            if line_id.inner() == id {
+                return Ok(true);
+            }
+        }
+        Ok(false)
     }
 }
 
-impl<P: AsRef<Path>> GetAll for DualLog<P> {
+impl<P: AsRef<Path>> GetTail for SimpleFileLog<P> {
+    type Error = FileError;
+    /// Reads one more line than asked for, to verify the oldest one too.
+    fn tail(&self, n: usize) -> Result<Vec<Transaction>, Self::Error> {
+        if n == 0 {
+            return Ok(Vec::new());
+        }
+        let format = self.format()?;
+        let txs = self.tail_lines(n + 1)?
+            .iter()
+            .map(|line| Transaction::parse_with_format(line, format))
+            .collect::<Result<Vec<_>, _>>()?;
+        if let Some(first) = txs.first() {
+            if first.id().inner() == TransactionId::MIN_ID {
+                self.verify(first, None)?;
+            }
+        }
+        for pair in txs.windows(2) {
+            self.verify(&pair[1], Some(&pair[0]))?;
+        }
+        let skip = txs.len().saturating_sub(n);
+        Ok(txs.into_iter().skip(skip).collect())
+    }
+}
+
+impl<P: AsRef<Path>> GetAll for SimpleFileLog<P> {
     type Error = FileError;
     fn get_all(&self) -> Result<Vec<Transaction>, Self::Error> {
-        Ok(self.full_log.get_all()?)
+        let format = self.format()?;
+        let mut vec = Vec::with_capacity(self.estimated_len());
+        for l in self.reader()?.records() {
+            let tx = Transaction::parse_with_format(&l?, format)?;
+            vec.push(tx);
+        }
+        if self.verify_on_read {
+            let start = self.verify_window
+                .map_or(0, |links| vec.len().saturating_sub(links));
+            for i in start..vec.len() {
+                let prev = if i > 0 { Some(&vec[i - 1]) } else { None };
+                self.verify(&vec[i], prev)?;
+            }
+        }
+        Ok(vec)
+    }
+
+    /// Only reads the first line.
+    fn first(&self) -> Result<Option<Transaction>, Self::Error> {
+        match self.reader()?.records().next() {
+            Some(line) => {
+                let format = self.format()?;
+                let tx = Transaction::parse_with_format(&line?, format)?;
+                self.verify(&tx, None)?;
+                Ok(Some(tx))
+            }
+            None => Ok(None),
+        }
+    }
+}
+
+#[derive(Debug)]
+pub struct DualLog<P: AsRef<Path>> {
+    full_log: FullTransactionLog,
+    file_log: SimpleFileLog<P>,
+    max_len: Option<usize>,
+    evicted: bool,
+    reject_duplicates: bool,
+    broadcast: Option<Arc<Broadcast>>,
+    len: usize,
+    max_transactions: Option<usize>,
+    max_bytes: Option<u64>,
+}
+
+impl<P: AsRef<Path>> DualLog<P> {
+    pub fn load(path: P) -> Result<Self, FileError> {
+        Self::load_from(SimpleFileLog::new(path))
+    }
+
+    /// Like `load`, but first recovers the file with
+    /// `SimpleFileLog::recover` instead of failing on corrupt lines.
+    /// Returns the log and the number of dropped lines.
+    pub fn load_recovering(path: P) -> Result<(Self, usize), FileError> {
+        Self::load_recovering_from(SimpleFileLog::new(path))
+    }
+
+    /// Like `load_recovering`, but keeps the configuration of `file_log`.
+    pub fn load_recovering_from(
+        mut file_log: SimpleFileLog<P>,
+    ) -> Result<(Self, usize), FileError> {
+        let dropped = file_log.recover()?;
+        Ok((Self::load_from(file_log)?, dropped))
+    }
+
+    /// Like `load`, but keeps the configuration of `file_log`, e.g. its
+    /// id allocator.
+    pub fn load_from(file_log: SimpleFileLog<P>) -> Result<Self, FileError> {
+        let mut full_log = FullTransactionLog::new();
+        for tx in file_log.get_all()? {
+            full_log.insert(tx);
+        }
+        Ok(DualLog {
+            len: full_log.log.len(),
+            full_log,
+            file_log,
+            max_len: None,
+            evicted: false,
+            reject_duplicates: false,
+            broadcast: None,
+            max_transactions: None,
+            max_bytes: None,
+        })
+    }
+
+    /// Refuses to create more than `max` transactions in total.
+    pub fn with_max_transactions(mut self, max: usize) -> Self {
+        self.max_transactions = Some(max);
+        self
+    }
+
+    /// Refuses to create transactions which would grow the file beyond
+    /// `max` bytes.
+    pub fn with_max_bytes(mut self, max: u64) -> Self {
+        self.max_bytes = Some(max);
+        self
+    }
+
+    /// Number of transactions in the log, including evicted ones.
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    /// Fails with `LOG_FULL` if `txs` would exceed a limit of the log.
+    fn check_quota(&self, txs: &[Transaction]) -> Result<(), FileError> {
+        let full = || FileError::Other(LOG_FULL.to_owned());
+        if let Some(max) = self.max_transactions {
+            if self.len + txs.len() > max {
+                return Err(full());
+            }
+        }
+        if let Some(max) = self.max_bytes {
+            let bytes: u64 = txs.iter().map(|t| t.line_len() as u64).sum();
+            if self.file_log.disk_bytes()? + bytes > max {
+                return Err(full());
+            }
+        }
+        Ok(())
+    }
+
+    /// Publishes every created transaction to `broadcast`.
+    pub fn with_broadcast(mut self, broadcast: Arc<Broadcast>) -> Self {
+        self.broadcast = Some(broadcast);
+        self
+    }
+
+    /// The broadcast set by `with_broadcast`.
+    pub fn broadcast(&self) -> Option<&Arc<Broadcast>> {
+        self.broadcast.as_ref()
+    }
+
+    /// Rejects transactions whose data equals the data of the previous
+    /// transaction, regardless of their timestamps.
+    pub fn with_reject_duplicates(mut self, reject_duplicates: bool) -> Self {
+        self.reject_duplicates = reject_duplicates;
+        self
+    }
+
+    /// Keeps at most `max_len` transactions in memory, evicting the lowest
+    /// ids first. Evicted transactions are read from the file on demand.
+    pub fn with_max_len(mut self, max_len: usize) -> Self {
+        self.max_len = Some(if max_len == 0 { 1 } else { max_len });
+        self.evict();
+        self
+    }
+
+    /// Rejects `data` if it repeats the last transaction and duplicates are
+    /// rejected.
+    fn check_duplicates(
+        &self,
+        data: Option<&TransactionData>,
+    ) -> Result<(), FileError> {
+        match (self.full_log.last()?, data) {
+            (Some(last), Some(data)) => self.check_duplicate(last.data(), data),
+            _ => Ok(()),
+        }
+    }
+
+    fn check_duplicate(
+        &self,
+        prev: &TransactionData,
+        data: &TransactionData,
+    ) -> Result<(), FileError> {
+        if self.reject_duplicates && prev == data {
+            return Err(FileError::Transaction(Error::IllegalArgument(
+                "duplicate of previous transaction".to_owned(),
+            )));
+        }
+        Ok(())
+    }
+
+    /// Adds transactions already written to the file to memory.
+    fn insert(&mut self, txs: &[Transaction]) {
+        self.len += txs.len();
+        for tx in txs {
+            self.full_log.insert(tx.clone());
+        }
+        self.evict();
+        if let Some(ref broadcast) = self.broadcast {
+            for tx in txs {
+                broadcast.publish(tx);
+            }
+        }
+    }
+
+    fn evict(&mut self) {
+        if let Some(max_len) = self.max_len {
+            while self.full_log.log.len() > max_len {
+                let first = *self.full_log.log.keys().next().unwrap();
+                self.full_log.remove(first);
+                self.evicted = true;
+            }
+        }
+    }
+}
+
+/// Differences between the file and the in-memory copy of a `DualLog`,
+/// listed by transaction id.
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+pub struct InconsistencyReport {
+    /// In the file, but not in memory.
+    pub missing: Vec<u32>,
+    /// In memory, but not in the file.
+    pub extra: Vec<u32>,
+    /// In both, but not equal.
+    pub mismatched: Vec<u32>,
+    /// The file could not be read back at all.
+    pub unreadable: Option<String>,
+}
+
+impl InconsistencyReport {
+    pub fn is_consistent(&self) -> bool {
+        self.missing.is_empty()
+            && self.extra.is_empty()
+            && self.mismatched.is_empty()
+            && self.unreadable.is_none()
+    }
+}
+
+impl<P: AsRef<Path>> DualLog<P> {
+    /// Re-reads the file and compares it id by id with the transactions in
+    /// memory. Evicted transactions are not expected to be in memory.
+    pub fn check_consistency(&self) -> Result<(), InconsistencyReport> {
+        let mut report = InconsistencyReport::default();
+        let file = match self.file_log.get_all() {
+            Ok(file) => file,
+            Err(e) => {
+                report.unreadable = Some(format!("{:?}", e));
+                return Err(report);
+            }
+        };
+        let first_in_memory = self.full_log.log.keys().next().cloned();
+        let mut file_ids = BTreeSet::new();
+        for tx in file {
+            let id = tx.id().inner();
+            file_ids.insert(id);
+            match self.full_log.log.get(&id) {
+                Some(mem) if mem.to_string() == tx.to_string() => {}
+                Some(_) => report.mismatched.push(id),
+                None if self.evicted
+                    && first_in_memory.map_or(true, |first| id < first) => {}
+                None => report.missing.push(id),
+            }
+        }
+        report.extra = self.full_log
+            .log
+            .keys()
+            .filter(|id| !file_ids.contains(id))
+            .cloned()
+            .collect();
+        if report.is_consistent() {
+            Ok(())
+        } else {
+            Err(report)
+        }
+    }
+
+    /// Reads the last transaction back from the file and verifies it, to
+    /// check the file is still accessible and intact.
+    pub fn check_file(&self) -> Result<(), FileError> {
+        self.file_log.last().map(|_| ())
+    }
+
+    /// Prunes the file with `SimpleFileLog::prune_older_than` and reloads
+    /// the remaining transactions.
+    pub fn prune_older_than(
+        &mut self,
+        cutoff: &TransactionTime,
+    ) -> Result<usize, FileError> {
+        let pruned = self.file_log.prune_older_than(cutoff)?;
+        if pruned > 0 {
+            self.full_log = FullTransactionLog::new();
+            for tx in self.file_log.get_all()? {
+                self.full_log.insert(tx);
+            }
+            self.len = self.full_log.log.len();
+            self.evicted = false;
+            self.evict();
+        }
+        Ok(pruned)
+    }
+
+    /// Whether the file was deleted while transactions are still kept in
+    /// memory, see `reset_if_missing`.
+    pub fn lost_file(&self) -> bool {
+        self.len > 0 && self.file_log.is_missing()
+    }
+
+    /// Forgets all transactions if the file was deleted, so the log reads
+    /// as empty like the file and the next create starts a new chain in a
+    /// new file. Returns whether anything was forgotten.
+    pub fn reset_if_missing(&mut self) -> bool {
+        if !self.lost_file() {
+            return false;
+        }
+        eprintln!(
+            "Warning: log file {:?} disappeared, forgetting {} transactions",
+            self.file_log.path.as_ref(),
+            self.len
+        );
+        self.full_log = FullTransactionLog::new();
+        self.len = 0;
+        self.evicted = false;
+        true
+    }
+
+    /// The bytes of the file, see `SimpleFileLog::export`.
+    pub fn export(&self) -> Result<Box<dyn Read + Send>, FileError> {
+        self.file_log.export()
+    }
+
+    /// Number of bytes the transactions take up on disk.
+    pub fn disk_bytes(&self) -> Result<u64, FileError> {
+        if self.evicted {
+            return self.file_log.disk_bytes();
+        }
+        Ok(self.full_log.log.values().map(|t| t.line_len() as u64).sum())
+    }
+
+    /// Makes sure the log belongs to the cluster `name`. An empty log gets
+    /// the genesis transaction appended, a non-empty log must start with it
+    /// or have pruned it, see `SimpleFileLog::pruned_genesis`.
+    pub fn ensure_genesis(
+        &mut self,
+        name: &str,
+    ) -> Result<Transaction, FileError> {
+        let genesis = TransactionData::genesis(name)?
+            .in_policy(self.format()?.policy)?;
+        let first = match self.get_by_id(TransactionId::MIN_ID)? {
+            Some(tx) => Some(tx),
+            None => self.file_log.pruned_genesis()?,
+        };
+        match first {
+            Some(ref tx) if tx.data() == &genesis => Ok(tx.clone()),
+            Some(tx) => Err(FileError::Other(format!(
+                "Genesis mismatch: log belongs to `{}`, not `{}`",
+                tx.data().text(),
+                name
+            ))),
+            None if self.last()?.is_none() => self.create(genesis, None),
+            None => Err(FileError::Other(
+                "Log has no genesis transaction".to_owned(),
+            )),
+        }
+    }
+}
+
+impl<P: AsRef<Path>> TransactionLog for DualLog<P> {
+    type Error = FileError;
+
+    fn create(
+        &mut self,
+        data: TransactionData,
+        time: Option<TransactionTime>,
+    ) -> Result<Transaction, Self::Error> {
+        self.reset_if_missing();
+        let format = self.format()?;
+        let tx = Transaction::with_prev_hash(
+            self.next_id()?,
+            format.stamp(time),
+            data.in_policy(format.policy)?,
+            self.last_hash()?.as_ref(),
+        );
+        self.append(std::slice::from_ref(&tx))?;
+        Ok(tx)
+    }
+
+    /// Also runs the duplicate and quota checks of `append`.
+    fn preview(
+        &self,
+        data: TransactionData,
+        time: Option<TransactionTime>,
+    ) -> Result<Transaction, Self::Error> {
+        let format = self.format()?;
+        let data = data.in_policy(format.policy)?;
+        self.check_duplicates(Some(&data))?;
+        let tx = Transaction::with_prev_hash(
+            self.next_id()?,
+            format.stamp(time),
+            data,
+            self.last_hash()?.as_ref(),
+        );
+        self.check_quota(std::slice::from_ref(&tx))?;
+        Ok(tx)
+    }
+
+    fn last(&self) -> Result<Option<Transaction>, Self::Error> {
+        Ok(self.full_log.last()?)
+    }
+
+    fn format(&self) -> Result<LogFormat, Self::Error> {
+        self.file_log.format()
+    }
+
+    fn last_hash(&self) -> Result<Option<TransactionHash>, Self::Error> {
+        Ok(self.full_log.last_hash()?)
+    }
+
+    fn next_id_after(
+        &self,
+        last: Option<&Transaction>,
+    ) -> Result<TransactionId, Self::Error> {
+        self.file_log.next_id_after(last)
+    }
+
+    fn append(&mut self, txs: &[Transaction]) -> Result<(), Self::Error> {
+        self.check_duplicates(txs.first().map(|t| t.data()))?;
+        for pair in txs.windows(2) {
+            self.check_duplicate(pair[0].data(), pair[1].data())?;
+        }
+        self.check_quota(txs)?;
+        self.file_log.append(txs)?;
+        self.insert(txs);
+        Ok(())
+    }
+}
+
+impl<P: AsRef<Path>> GetById for DualLog<P> {
+    type Error = FileError;
+    fn get_by_id(&self, id: u32) -> Result<Option<Transaction>, Self::Error> {
+        match self.full_log.get_by_id(id)? {
+            None if self.evicted => self.file_log.get_by_id(id),
+            tx => Ok(tx),
+        }
+    }
+
+    fn contains(&self, id: u32) -> Result<bool, Self::Error> {
+        match self.full_log.contains(id)? {
+            false if self.evicted => self.file_log.contains(id),
+            contained => Ok(contained),
+        }
+    }
+}
+
+impl<P: AsRef<Path>> GetTail for DualLog<P> {
+    type Error = FileError;
+    fn tail(&self, n: usize) -> Result<Vec<Transaction>, Self::Error> {
+        if self.evicted && n > self.full_log.log.len() {
+            return self.file_log.tail(n);
+        }
+        Ok(self.full_log.tail(n)?)
+    }
+}
+
+impl<P: AsRef<Path>> GetAll for DualLog<P> {
+    type Error = FileError;
+    fn get_all(&self) -> Result<Vec<Transaction>, Self::Error> {
+        if self.evicted {
+            return self.file_log.get_all();
+        }
+        Ok(self.full_log.get_all()?)
+    }
+
+    fn first(&self) -> Result<Option<Transaction>, Self::Error> {
+        if self.evicted {
+            return self.file_log.first();
+        }
+        Ok(self.full_log.first()?)
+    }
+
+    fn get_by_hash(
+        &self,
+        hash: &TransactionHash,
+    ) -> Result<Option<Transaction>, Self::Error> {
+        match self.full_log.get_by_hash(hash)? {
+            None if self.evicted => self.file_log.get_by_hash(hash),
+            tx => Ok(tx),
+        }
+    }
+}
+
+/// Write only log streaming one line per transaction into any `Write`,
+/// e.g. stdout, a pipe or a socket. The sink can't be read back, so the
+/// chain continues from the last transaction kept in memory, which only
+/// `create` and `append` use: `last()` fails like `get_all`.
+#[derive(Debug)]
+pub struct WriterLog<W: Write> {
+    writer: W,
+    last: Option<Transaction>,
+}
+
+impl<W: Write> WriterLog<W> {
+    /// Starts a new chain.
+    pub fn new(writer: W) -> Self {
+        Self::after(writer, None)
+    }
+
+    /// Continues the chain of another log ending with `last`, e.g. to copy
+    /// its new transactions to another process.
+    pub fn after(writer: W, last: Option<Transaction>) -> Self {
+        WriterLog { writer, last }
+    }
+
+    pub fn into_inner(self) -> W {
+        self.writer
+    }
+}
+
+impl<W: Write> TransactionLog for WriterLog<W> {
+    type Error = FileError;
+
+    fn create(
+        &mut self,
+        data: TransactionData,
+        time: Option<TransactionTime>,
+    ) -> Result<Transaction, Self::Error> {
+        let tx = Transaction::new(
+            self.next_id()?,
+            time.unwrap_or_else(TransactionTime::current),
+            data,
+            self.last.as_ref(),
+        );
+        self.append(std::slice::from_ref(&tx))?;
+        Ok(tx)
+    }
+
+    /// Always fails, a sink can't be read back.
+    fn last(&self) -> Result<Option<Transaction>, Self::Error> {
+        Err(FileError::Other("cannot read a write only log".to_owned()))
+    }
+
+    fn next_id(&self) -> Result<TransactionId, Self::Error> {
+        self.next_id_after(self.last.as_ref())
+    }
+
+    fn last_hash(&self) -> Result<Option<TransactionHash>, Self::Error> {
+        Ok(self.last.as_ref().map(|t| t.hash().clone()))
+    }
+
+    fn create_batch(
+        &mut self,
+        items: Vec<(TransactionData, Option<TransactionTime>)>,
+    ) -> Result<Vec<Transaction>, Self::Error> {
+        let mut last = self.last.clone();
+        let mut txs = Vec::with_capacity(items.len());
+        for (data, time) in items {
+            let tx = Transaction::new(
+                self.next_id_after(last.as_ref())?,
+                time.unwrap_or_else(TransactionTime::current),
+                data,
+                last.as_ref(),
+            );
+            last = Some(tx.clone());
+            txs.push(tx);
+        }
+        if !txs.is_empty() {
+            self.append(&txs)?;
+        }
+        Ok(txs)
+    }
+
+    /// Writes all lines with a single write and flushes them, so a reader
+    /// on the other end sees complete transactions.
+    fn append(&mut self, txs: &[Transaction]) -> Result<(), Self::Error> {
+        let mut prev = self.last.as_ref();
+        for tx in txs {
+            verify_transaction(tx, prev)?;
+            prev = Some(tx);
+        }
+        let lines: String = txs.iter().map(|tx| format!("{}\n", tx)).collect();
+        self.writer.write_all(lines.as_bytes())?;
+        self.writer.flush()?;
+        if let Some(tx) = txs.last() {
+            self.last = Some(tx.clone());
+        }
+        Ok(())
+    }
+}
+
+impl<W: Write> GetAll for WriterLog<W> {
+    type Error = FileError;
+    /// Always fails, a sink can't be read back.
+    fn get_all(&self) -> Result<Vec<Transaction>, Self::Error> {
+        Err(FileError::Other("cannot read a write only log".to_owned()))
+    }
+}
+
+
+#[cfg(test)]
+mod test {
+
+    use super::*;
+    use crate::id_allocator::StridedAllocator;
+    use std::env;
+    use std::fs;
+    use std::path::PathBuf;
+    use std::process;
+
+    #[test]
+    fn export_is_exact() {
+        let path = temp_log("export");
+        let mut file = SimpleFileLog::new(&path);
+        fill(&mut file, 3);
+        let mut on_disk = Vec::new();
+        File::open(&path).unwrap().read_to_end(&mut on_disk).unwrap();
+
+        let dual = DualLog::load(&path).unwrap();
+        for mut export in vec![file.export().unwrap(), dual.export().unwrap()] {
+            let mut exported = Vec::new();
+            export.read_to_end(&mut exported).unwrap();
+            assert_eq!(exported, on_disk);
+        }
+        fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn format_header_is_read_back() {
+        let path = temp_log("format_header");
+        let iso = LogFormat {
+            time: TimeFormat::Iso,
+            ..LogFormat::default()
+        };
+        let file = SimpleFileLog::new(&path).with_format(iso);
+        let mut log = DualLog::load_from(file).unwrap();
+        log.create(TransactionData::new(1, 2, "now").unwrap(), None).unwrap();
+        // a given time is converted to the format of the log
+        let time = "041017-10:00:00".parse().unwrap();
+        log.create(TransactionData::new(1, 2, "then").unwrap(), Some(time))
+            .unwrap();
+
+        let mut contents = String::new();
+        File::open(&path)
+            .unwrap()
+            .read_to_string(&mut contents)
+            .unwrap();
+        assert!(contents.starts_with("# format time=iso policy=strict\n"));
+        assert!(contents.contains(";2017-10-04T10:00:00;01;02;then;"));
+
+        // the header wins over the format of the reader
+        let reloaded = DualLog::load(&path).unwrap();
+        assert_eq!(reloaded.format().unwrap(), iso);
+        assert_eq!(reloaded.get_all().unwrap().len(), 2);
+        let file = SimpleFileLog::new(&path);
+        assert_eq!(file.last().unwrap().unwrap().data().text(), "then");
+        file.verify_chain().unwrap();
+
+        // a compact log can't take an iso line, it would not parse back
+        let first = SimpleFileLog::new(&path).first().unwrap().unwrap();
+        let compact_path = temp_log("format_compact");
+        let mut compact = SimpleFileLog::new(&compact_path);
+        assert!(compact.append(&[first]).is_err());
+        fs::remove_file(&path).unwrap();
+        fs::remove_file(&compact_path).unwrap();
+    }
+
+    #[test]
+    fn escaped_text_is_read_back() {
+        let path = temp_log("escaped_text");
+        let escaped = LogFormat {
+            policy: CharPolicy::Escaped,
+            ..LogFormat::default()
+        };
+        let text = "semi;colon\nnew line\\";
+        let data = TransactionData::with_policy(
+            1,
+            2,
+            text,
+            DataFormat::V1,
+            CharPolicy::Escaped,
+        ).unwrap();
+        let file = SimpleFileLog::new(&path).with_format(escaped);
+        DualLog::load_from(file).unwrap().create(data, None).unwrap();
+
+        let reloaded = DualLog::load(&path).unwrap();
+        let tx = reloaded.last().unwrap().unwrap();
+        assert_eq!(tx.data().text(), text);
+        assert_eq!(tx.data().policy(), CharPolicy::Escaped);
+        fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn verify_window() {
+        let path = temp_log("verify_window");
+        fill(&mut SimpleFileLog::new(&path), 6);
+        let tamper = |id: u32| {
+            let mut contents = String::new();
+            File::open(&path)
+                .unwrap()
+                .read_to_string(&mut contents)
+                .unwrap();
+            let lines: Vec<String> = contents
+                .lines()
+                .map(|l| if l.starts_with(&format!("{:08};", id)) {
+                    l.replacen(";01;02;", ";01;03;", 1)
+                } else {
+                    l.to_owned()
+                })
+                .collect();
+            File::create(&path)
+                .unwrap()
+                .write_all(format!("{}\n", lines.join("\n")).as_bytes())
+                .unwrap();
+        };
+        let windowed = SimpleFileLog::new(&path).with_verify_window(2);
+        assert_eq!(windowed.get_all().unwrap().len(), 6);
+
+        // the window covers the links into transactions 5 and 6
+        tamper(2);
+        assert_eq!(windowed.get_all().unwrap().len(), 6);
+        assert!(windowed.verify_chain().is_err());
+        assert!(SimpleFileLog::new(&path).get_all().is_err());
+
+        tamper(5);
+        match windowed.get_all() {
+            Err(FileError::Verify(e)) => assert_eq!(e.id(), 5),
+            other => panic!("unexpected {:?}", other),
+        }
+
+        let whole = SimpleFileLog::new(&path).with_verify_window(100);
+        assert!(whole.get_all().is_err());
+        fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn read_back_created() {
+        let path = temp_log("read_back");
+        let mut log = SimpleFileLog::new(&path).with_read_back(true);
+        let data = || TransactionData::new(1, 2, "read back").unwrap();
+        let tx = log.create(data(), None).unwrap();
+        assert!(log.check_written(&tx).is_ok());
+
+        // a write cut short leaves a line which doesn't match
+        let next = log.preview(data(), None).unwrap();
+        let line = next.to_string();
+        OpenOptions::new()
+            .append(true)
+            .open(&path)
+            .unwrap()
+            .write_all(format!("{}\n", &line[..line.len() / 2]).as_bytes())
+            .unwrap();
+        match log.check_written(&next) {
+            Err(FileError::Other(msg)) => assert!(msg.contains("00000002")),
+            other => panic!("unexpected {:?}", other),
+        }
+        fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn preview_stores_nothing() {
+        let path = temp_log("preview");
+        let mut log = DualLog::load(&path)
+            .unwrap()
+            .with_reject_duplicates(true)
+            .with_max_transactions(2);
+        let data = |text: &str| TransactionData::new(1, 2, text).unwrap();
+        let time = || Some("041017-10:00:00".parse().unwrap());
+
+        let preview = log.preview(data("first"), time()).unwrap();
+        assert_eq!(preview.id().inner(), 1);
+        assert_eq!(log.len(), 0);
+        assert_eq!(log.next_id().unwrap().inner(), 1);
+        assert!(SimpleFileLog::new(&path).get_all().unwrap().is_empty());
+        let created = log.create(data("first"), time()).unwrap();
+        assert_eq!(created.to_string(), preview.to_string());
+
+        // previews fail like the write would
+        assert!(log.preview(data("first"), time()).is_err());
+        log.create(data("second"), time()).unwrap();
+        match log.preview(data("third"), time()) {
+            Err(FileError::Other(ref msg)) if msg == LOG_FULL => {}
+            other => panic!("unexpected {:?}", other),
+        }
+        assert_eq!(log.len(), 2);
+        fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn prune_old_transactions() {
+        let path = temp_log("prune");
+        let mut log = DualLog::load(&path).unwrap();
+        for (i, ts) in ["011017", "021017", "031017", "041017"]
+            .iter()
+            .enumerate()
+        {
+            let data = TransactionData::new(1, 2, format!("tx {}", i));
+            let ts = format!("{}-10:00:00", ts).parse().unwrap();
+            log.create(data.unwrap(), Some(ts)).unwrap();
+        }
+        let time = |t: &str| t.parse::<TransactionTime>().unwrap();
+        let before = log.get_all().unwrap();
+
+        assert_eq!(log.prune_older_than(&time("011017-00:00:00")).unwrap(), 0);
+        for cutoff in &["051017-00:00:00", "041017-10:00:01"] {
+            match log.prune_older_than(&time(cutoff)) {
+                Err(FileError::Other(ref msg)) if msg == DANGLING_CHAIN => {}
+                other => panic!("unexpected {:?}", other),
+            }
+        }
+        assert_eq!(log.prune_older_than(&time("021017-12:00:00")).unwrap(), 2);
+
+        // the remaining chain verifies from its new anchor
+        let file = SimpleFileLog::new(&path);
+        let after = file.get_all().unwrap();
+        let ids: Vec<u32> = after.iter().map(|t| t.id().inner()).collect();
+        assert_eq!(ids, vec![3, 4]);
+        assert!(verify_with_prev_hash(&after[0], None).is_ok());
+        assert_ne!(after[0].hash().as_slice(), before[2].hash().as_slice());
+        assert_eq!(after[1].data(), before[3].data());
+        assert_eq!(file.last().unwrap().unwrap().id().inner(), 4);
+        assert_eq!(file.tail(5).unwrap().len(), 2);
+        assert_eq!(log.len(), 2);
+        assert_eq!(log.first().unwrap().unwrap().id().inner(), 3);
+
+        let mut contents = String::new();
+        File::open(&path)
+            .unwrap()
+            .read_to_string(&mut contents)
+            .unwrap();
+        let header =
+            format!("# genesis {}\n# pruned up to 00000002", before[0]);
+        assert!(contents.starts_with(&header));
+        assert!(contents.contains(&before[1].hash().to_string()));
+
+        let tx = log.create(TransactionData::new(1, 2, "later").unwrap(), None);
+        assert_eq!(tx.unwrap().id().inner(), 5);
+        assert_eq!(DualLog::load(&path).unwrap().len(), 3);
+
+        // an older transaction after a newer one would leave a gap
+        let gap = temp_log("prune_gap");
+        let mut log = SimpleFileLog::new(&gap);
+        for ts in &["011017", "031017", "021017", "041017"] {
+            let ts = format!("{}-10:00:00", ts).parse().unwrap();
+            let data = TransactionData::new(1, 2, "gap").unwrap();
+            log.create(data, Some(ts)).unwrap();
+        }
+        assert!(log.prune_older_than(&time("021017-12:00:00")).is_err());
+        assert_eq!(log.get_all().unwrap().len(), 4);
+
+        // the original is kept next to the pruned log
+        let backup = SimpleFileLog::new(&path).sibling(".pruned");
+        let saved = SimpleFileLog::new(&backup).get_all().unwrap();
+        let lines = |txs: &[Transaction]| {
+            txs.iter().map(|t| t.to_string()).collect::<Vec<_>>()
+        };
+        assert_eq!(lines(&saved), lines(&before));
+        fs::remove_file(&backup).unwrap();
+        fs::remove_file(&path).unwrap();
+        fs::remove_file(&gap).unwrap();
+    }
+
+    #[test]
+    fn failed_prune_keeps_log() {
+        let path = temp_log("prune_failed");
+        let mut log = SimpleFileLog::new(&path);
+        for day in &["01", "02", "03"] {
+            let data = TransactionData::new(1, 2, "kept").unwrap();
+            let ts = format!("{}1017-10:00:00", day).parse().unwrap();
+            log.create(data, Some(ts)).unwrap();
+        }
+        let read = || {
+            let mut contents = String::new();
+            File::open(&path)
+                .unwrap()
+                .read_to_string(&mut contents)
+                .unwrap();
+            contents
+        };
+        let before = read();
+
+        // a directory in the way of the pruned file fails the write
+        let tmp = log.sibling(".tmp");
+        fs::create_dir(&tmp).unwrap();
+        let cutoff = "021017-12:00:00".parse().unwrap();
+        assert!(log.prune_older_than(&cutoff).is_err());
+        assert_eq!(read(), before);
+        assert_eq!(log.get_all().unwrap().len(), 3);
+        assert!(!log.sibling(".pruned").exists());
+
+        fs::remove_dir(&tmp).unwrap();
+        fs::remove_file(&path).unwrap();
+    }
+
+    fn assert_send_sync<T: Send + Sync>() {}
+
+    /// Logs are shared between Rocket's worker threads behind an `RwLock`,
+    /// which needs them to be `Send` and `Sync` for concurrent readers.
+    #[test]
+    fn logs_are_send_sync() {
+        assert_send_sync::<FullTransactionLog>();
+        assert_send_sync::<SimpleFileLog<String>>();
+        assert_send_sync::<SimpleFileLog<PathBuf>>();
+        assert_send_sync::<DualLog<String>>();
+        assert_send_sync::<DualLog<PathBuf>>();
+        assert_send_sync::<WriterLog<Vec<u8>>>();
+        assert_send_sync::<FileError>();
+    }
+
+    fn temp_log(name: &str) -> PathBuf {
+        let path = env::temp_dir()
+            .join(format!("tx_log_test_{}_{}.txt", name, process::id()));
+        let _ = fs::remove_file(&path);
+        File::create(&path).unwrap();
+        path
+    }
+
+    fn fill<L: TransactionLog>(log: &mut L, n: usize) {
+        for i in 0..n {
+            log.create(
+                TransactionData::new(1, 2, format!("Transaction Nr. {}", i))
+                    .unwrap(),
+                Some("041017-10:00:00".parse().unwrap()),
+            ).unwrap();
+        }
+    }
+
+    /// Drives a fresh log through a fixed scenario and checks the invariants
+    /// every log type has to uphold.
+    fn conformance_suite<L, F>(make: F)
+    where
+        L: TransactionLog + GetAll + GetById + GetTail,
+        F: Fn() -> L,
+    {
+        let mut log = make();
+        assert!(log.last().unwrap().is_none());
+        assert!(log.tail(3).unwrap().is_empty());
+        assert_eq!(log.next_id().unwrap(), TransactionId::default());
+        assert!(log.get_all().unwrap().is_empty());
+        assert!(log.first().unwrap().is_none());
+        assert!(log.get_by_id(TransactionId::MIN_ID).unwrap().is_none());
+
+        let mut created = Vec::new();
+        for i in 0..5 {
+            let expected_id = log.next_id().unwrap();
+            let tx = log.create(
+                TransactionData::new(i, 2, format!("Conformance {}", i))
+                    .unwrap(),
+                Some("041017-10:00:00".parse().unwrap()),
+            ).unwrap();
+            assert_eq!(tx.id(), &expected_id);
+            assert_eq!(
+                log.last().unwrap().map(|t| t.to_string()),
+                Some(tx.to_string())
+            );
+            created.push(tx);
+        }
+
+        let all = log.get_all().unwrap();
+        assert_eq!(all.len(), created.len());
+        assert_eq!(
+            log.first().unwrap().map(|t| t.to_string()),
+            Some(all[0].to_string())
+        );
+        let mut prev: Option<&Transaction> = None;
+        for (tx, expected) in all.iter().zip(created.iter()) {
+            assert_eq!(tx.to_string(), expected.to_string());
+            assert_eq!(verify_transaction(tx, prev), Ok(()));
+            assert_eq!(
+                log.get_by_id(tx.id().inner())
+                    .unwrap()
+                    .map(|t| t.to_string()),
+                Some(tx.to_string())
+            );
+            prev = Some(tx);
+        }
+        for id in 0..8 {
+            assert_eq!(
+                log.contains(id).unwrap(),
+                log.get_by_id(id).unwrap().is_some()
+            );
+        }
+        assert!(log.get_by_id(6).unwrap().is_none());
+        assert_eq!(log.next_id().unwrap().inner(), 6);
+
+        let strings = |txs: &[Transaction]| {
+            txs.iter().map(|t| t.to_string()).collect::<Vec<_>>()
+        };
+        let after = |i: usize| {
+            log.after_hash(created[i].hash()).unwrap().map(|txs| strings(&txs))
+        };
+        assert_eq!(after(1), Some(strings(&created[2..])));
+        assert_eq!(after(4), Some(Vec::new()));
+        let unknown: TransactionHash = "0".repeat(64).parse().unwrap();
+        assert!(log.after_hash(&unknown).unwrap().is_none());
+        for tx in &created {
+            let found = log.get_by_hash(tx.hash()).unwrap();
+            assert_eq!(found.map(|t| t.to_string()), Some(tx.to_string()));
+        }
+        assert!(log.get_by_hash(&unknown).unwrap().is_none());
+
+        assert_eq!(strings(&log.tail(1).unwrap()), strings(&created[4..]));
+        assert_eq!(strings(&log.tail(3).unwrap()), strings(&created[2..]));
+        assert_eq!(strings(&log.tail(10).unwrap()), strings(&created));
+        assert!(log.tail(0).unwrap().is_empty());
+    }
+
+    #[test]
+    fn conformance_full_log() {
+        conformance_suite(FullTransactionLog::new);
+    }
+
+    #[test]
+    fn conformance_simple_file_log() {
+        let path = temp_log("conformance_simple");
+        conformance_suite(|| SimpleFileLog::new(&path));
+        fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn conformance_dual_log() {
+        let path = temp_log("conformance_dual");
+        conformance_suite(|| DualLog::load(&path).unwrap());
+        fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn last_with_chunk_sizes() {
+        let path = temp_log("chunk_sizes");
+        fill(&mut SimpleFileLog::new(&path), 20);
+
+        let small = SimpleFileLog::new(&path).with_chunk_size(16);
+        let large = SimpleFileLog::new(&path).with_chunk_size(1024 * 1024);
+        let last_small = small.last().unwrap().unwrap();
+        let last_large = large.last().unwrap().unwrap();
+        assert_eq!(last_small.id().inner(), 20);
+        assert_eq!(last_small.to_string(), last_large.to_string());
+
+        fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn genesis_created_and_checked() {
+        let path = temp_log("genesis");
+        {
+            let mut log = DualLog::load(&path).unwrap();
+            let genesis = log.ensure_genesis("cluster-a").unwrap();
+            assert_eq!(genesis.id().inner(), TransactionId::MIN_ID);
+            fill(&mut log, 3);
+        }
+        let mut log = DualLog::load(&path).unwrap();
+        assert!(log.ensure_genesis("cluster-a").is_ok());
+        assert!(log.ensure_genesis("cluster-b").is_err());
+        assert_eq!(log.next_id().unwrap().inner(), 5);
+
+        fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn genesis_survives_pruning() {
+        let path = temp_log("genesis_pruned");
+        let time = |t: &str| t.parse::<TransactionTime>().unwrap();
+        let mut log = DualLog::load(&path).unwrap();
+        let data = TransactionData::genesis("cluster-a").unwrap();
+        let genesis = log.create(data, Some(time("031017-10:00:00"))).unwrap();
+        fill(&mut log, 3);
+        let cutoff = time("041017-00:00:00");
+        assert_eq!(log.prune_older_than(&cutoff).unwrap(), 1);
+        assert!(log.ensure_genesis("cluster-a").is_ok());
+
+        // pruning again and reloading keeps the genesis
+        let data = TransactionData::new(1, 2, "later").unwrap();
+        log.create(data, Some(time("051017-10:00:00"))).unwrap();
+        let cutoff = time("051017-00:00:00");
+        assert_eq!(log.prune_older_than(&cutoff).unwrap(), 3);
+        let mut log = DualLog::load(&path).unwrap();
+        assert!(log.get_by_id(TransactionId::MIN_ID).unwrap().is_none());
+        let kept = log.file_log.pruned_genesis().unwrap().unwrap();
+        assert_eq!(kept.hash().as_slice(), genesis.hash().as_slice());
+        assert!(log.ensure_genesis("cluster-a").is_ok());
+        assert!(log.ensure_genesis("cluster-b").is_err());
+        assert_eq!(log.next_id().unwrap().inner(), 6);
+
+        fs::remove_file(log.file_log.sibling(".pruned")).unwrap();
+        fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn bounded_dual_log() {
+        let path = temp_log("bounded");
+        let mut log = DualLog::load(&path).unwrap().with_max_len(3);
+        fill(&mut log, 5);
+        assert_eq!(log.full_log.log.len(), 3);
+        assert!(log.full_log.get_by_id(1).unwrap().is_none());
+        assert!(log.contains(1).unwrap());
+
+        assert_eq!(log.get_by_id(1).unwrap().unwrap().id().inner(), 1);
+        assert_eq!(log.get_by_id(5).unwrap().unwrap().id().inner(), 5);
+        assert!(log.get_by_id(6).unwrap().is_none());
+        assert_eq!(log.get_all().unwrap().len(), 5);
+        assert_eq!(log.first().unwrap().unwrap().id().inner(), 1);
+        assert_eq!(log.last().unwrap().unwrap().id().inner(), 5);
+        assert_eq!(log.next_id().unwrap().inner(), 6);
+
+        let reloaded = DualLog::load(&path).unwrap().with_max_len(2);
+        assert_eq!(reloaded.full_log.log.len(), 2);
+        assert_eq!(reloaded.get_by_id(2).unwrap().unwrap().id().inner(), 2);
+
+        fs::remove_file(&path).unwrap();
+    }
+
+    fn assert_hash_index(log: &FullTransactionLog) {
+        assert_eq!(log.by_hash.len(), log.log.len());
+        for (key, id) in &log.by_hash {
+            assert_eq!(&hash_key(log.log[id].hash()), key);
+        }
+    }
+
+    #[test]
+    fn hash_index_follows_log() {
+        let path = temp_log("hash_index");
+        let mut log = DualLog::load(&path).unwrap().with_max_len(3);
+        fill(&mut log, 2);
+        assert_hash_index(&log.full_log);
+        fill(&mut log, 3);
+        // evicted transactions leave the index along with the log
+        assert_hash_index(&log.full_log);
+        assert_eq!(log.full_log.by_hash.len(), 3);
+
+        let reloaded = DualLog::load(&path).unwrap();
+        assert_hash_index(&reloaded.full_log);
+        assert_eq!(reloaded.full_log.by_hash.len(), 5);
+        for tx in reloaded.get_all().unwrap() {
+            for found in &[
+                log.get_by_hash(tx.hash()).unwrap(),
+                reloaded.get_by_hash(tx.hash()).unwrap(),
+                reloaded.get_by_id(tx.id().inner()).unwrap(),
+            ] {
+                let found = found.as_ref().map(|t| t.to_string());
+                assert_eq!(found, Some(tx.to_string()));
+            }
+        }
+
+        // a parsed hash keeps its text, but is still found
+        let tx = reloaded.last().unwrap().unwrap();
+        let padded: TransactionHash =
+            format!(" {} ", tx.hash()).parse().unwrap();
+        assert!(reloaded.get_by_hash(&padded).unwrap().is_some());
+
+        fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn deleted_file_reads_empty() {
+        let path = temp_log("deleted");
+        let mut file = SimpleFileLog::new(&path);
+        fill(&mut file, 2);
+        let mut dual = DualLog::load(&path).unwrap();
+        fs::remove_file(&path).unwrap();
+
+        assert!(file.is_missing());
+        assert!(file.get_all().unwrap().is_empty());
+        assert!(file.last().unwrap().is_none());
+        assert!(file.tail(3).unwrap().is_empty());
+        assert!(file.get_by_id(1).unwrap().is_none());
+        assert_eq!(file.disk_bytes().unwrap(), 0);
+        let mut exported = Vec::new();
+        file.export().unwrap().read_to_end(&mut exported).unwrap();
+        assert!(exported.is_empty());
+
+        assert_eq!(dual.len(), 2);
+        assert!(dual.reset_if_missing());
+        assert!(!dual.reset_if_missing());
+        assert_eq!(dual.len(), 0);
+        assert!(dual.last().unwrap().is_none());
+        assert!(dual.get_all().unwrap().is_empty());
+
+        let data = TransactionData::new(1, 2, "anew").unwrap();
+        let tx = dual.create(data, None).unwrap();
+        assert_eq!(tx.id().inner(), TransactionId::MIN_ID);
+        assert!(!file.is_missing());
+        assert_eq!(file.get_all().unwrap().len(), 1);
+
+        fs::remove_file(&path).unwrap();
+    }
+
+    fn assert_last_hash<L: TransactionLog>(log: &L) {
+        assert_eq!(
+            log.last_hash().unwrap().map(|h| h.to_string()),
+            log.last().unwrap().map(|t| t.hash().to_string())
+        );
+    }
+
+    #[test]
+    fn last_hash_matches_last() {
+        let path = temp_log("last_hash");
+        let mut full = FullTransactionLog::new();
+        assert_last_hash(&full);
+        fill(&mut full, 3);
+        assert_last_hash(&full);
+
+        let mut dual = DualLog::load(&path).unwrap();
+        assert_last_hash(&dual);
+        assert_last_hash(&SimpleFileLog::new(&path));
+        fill(&mut dual, 3);
+        assert_last_hash(&dual);
+        assert_last_hash(&SimpleFileLog::new(&path));
+
+        fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn get_all_reserves_estimate() {
+        let path = temp_log("estimate");
+        let mut log = SimpleFileLog::new(&path);
+        assert_eq!(log.estimated_len(), 0);
+        fill(&mut log, 1_000);
+        // within a factor of two, so reading grows the vec at most once
+        // instead of about ten times starting from an empty vec
+        let estimate = log.estimated_len();
+        assert!(estimate > 500 && estimate <= 1_000, "{}", estimate);
+
+        let all = log.get_all().unwrap();
+        assert_eq!(all.len(), 1_000);
+        assert!(all.capacity() <= 2 * estimate);
+        let mut full = FullTransactionLog::new();
+        fill(&mut full, 1_000);
+        let expected = full.get_all().unwrap();
+        for (tx, expected) in all.iter().zip(&expected) {
+            assert_eq!(tx.to_string(), expected.to_string());
+        }
+        fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn get_all_without_verify() {
+        let path = temp_log("no_verify");
+        fill(&mut SimpleFileLog::new(&path), 5);
+        let strings = |log: &SimpleFileLog<&PathBuf>| {
+            let all = log.get_all().unwrap();
+            all.iter().map(|t| t.to_string()).collect::<Vec<_>>()
+        };
+        let verified = strings(&SimpleFileLog::new(&path));
+        let unverified =
+            strings(&SimpleFileLog::new(&path).with_verify_on_read(false));
+        assert_eq!(verified, unverified);
+
+        let mut contents = String::new();
+        File::open(&path)
+            .unwrap()
+            .read_to_string(&mut contents)
+            .unwrap();
+        File::create(&path)
+            .unwrap()
+            .write_all(contents.replace("Nr. 2", "Nr. X").as_bytes())
+            .unwrap();
+        assert!(SimpleFileLog::new(&path).get_all().is_err());
+        let tampered = SimpleFileLog::new(&path).with_verify_on_read(false);
+        let all = tampered.get_all().unwrap();
+        assert_eq!(all[2].data().text(), "Transaction Nr. X");
+        fs::remove_file(&path).unwrap();
+    }
+
+    // run with: $ cargo test bench_verify_on_read -- --ignored --nocapture
+    #[test]
+    #[ignore]
+    fn bench_verify_on_read() {
+        use std::time::Instant;
+        let path = temp_log("bench_verify");
+        let txs = {
+            let mut log = FullTransactionLog::new();
+            fill(&mut log, 20_000);
+            log.get_all().unwrap()
+        };
+        SimpleFileLog::new(&path).append(&txs).unwrap();
+        for &verify in &[true, false] {
+            let log = SimpleFileLog::new(&path).with_verify_on_read(verify);
+            let start = Instant::now();
+            for _ in 0..5 {
+                assert_eq!(log.get_all().unwrap().len(), txs.len());
+            }
+            println!("verify_on_read {}: {:?}", verify, start.elapsed() / 5);
+        }
+        fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn disk_bytes_match_file_size() {
+        let path = temp_log("disk_bytes");
+        let mut log = DualLog::load(&path).unwrap();
+        assert_eq!(log.disk_bytes().unwrap(), 0);
+        fill(&mut log, 5);
+        log.create(TransactionData::new(0, 0, "Grüße").unwrap(), None)
+            .unwrap();
+
+        let file_size = fs::metadata(&path).unwrap().len();
+        let sum: usize =
+            log.get_all().unwrap().iter().map(|t| t.line_len()).sum();
+        assert_eq!(sum as u64, file_size);
+        assert_eq!(log.disk_bytes().unwrap(), file_size);
+        assert_eq!(SimpleFileLog::new(&path).disk_bytes().unwrap(), file_size);
+
+        fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn reject_duplicates() {
+        let path = temp_log("duplicates");
+        let mut log =
+            DualLog::load(&path).unwrap().with_reject_duplicates(true);
+        let data = TransactionData::new(1, 2, "hello").unwrap();
+        log.create(data.clone(), Some("041017-10:00:00".parse().unwrap()))
+            .unwrap();
+        let later = Some("041017-11:00:00".parse().unwrap());
+        match log.create(data.clone(), later) {
+            Err(FileError::Transaction(e)) => assert_eq!(
+                e,
+                Error::IllegalArgument(
+                    "duplicate of previous transaction".to_owned()
+                )
+            ),
+            other => panic!("unexpected {:?}", other),
+        }
+        log.create(TransactionData::new(1, 2, "world").unwrap(), None)
+            .unwrap();
+        log.create(data.clone(), None).unwrap();
+        assert_eq!(log.get_all().unwrap().len(), 3);
+
+        let other = TransactionData::new(1, 2, "other").unwrap();
+        assert!(log.create_batch(vec![(data.clone(), None)]).is_err());
+        assert!(
+            log.create_batch(vec![(other.clone(), None), (other, None)])
+                .is_err()
+        );
+        assert_eq!(log.get_all().unwrap().len(), 3);
+
+        fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn relaxed_duplicates_after_reload() {
+        let path = temp_log("relaxed_duplicates");
+        let relaxed = LogFormat {
+            policy: CharPolicy::Relaxed,
+            ..LogFormat::default()
+        };
+        let text = "tab\there";
+        let data = TransactionData::with_policy(
+            1,
+            2,
+            text,
+            DataFormat::V1,
+            CharPolicy::Relaxed,
+        ).unwrap();
+        let file = SimpleFileLog::new(&path).with_format(relaxed);
+        let mut log = DualLog::load_from(file).unwrap();
+        log.create(data.clone(), None).unwrap();
+        // strict data takes the policy of the log
+        log.create(TransactionData::new(1, 2, "plain").unwrap(), None)
+            .unwrap();
+        log.create(data.clone(), None).unwrap();
+
+        let mut reloaded =
+            DualLog::load(&path).unwrap().with_reject_duplicates(true);
+        let last = reloaded.last().unwrap().unwrap();
+        assert_eq!(last.data(), &data);
+        assert_eq!(last.data().text(), text);
+        assert!(reloaded.create(data, None).is_err());
+
+        // a line the log could not parse back is refused
+        let strict_path = temp_log("relaxed_into_strict");
+        let mut strict = SimpleFileLog::new(&strict_path);
+        let first = reloaded.first().unwrap().unwrap();
+        assert!(strict.append(&[first]).is_err());
+        fs::remove_file(&path).unwrap();
+        fs::remove_file(&strict_path).unwrap();
+    }
+
+    #[cfg(feature = "flate2")]
+    #[test]
+    fn gzip_log() {
+        use flate2::Compression;
+        use flate2::write::GzEncoder;
+
+        let path = temp_log("gzip_plain");
+        fill(&mut SimpleFileLog::new(&path), 5);
+        let gz_path = path.with_extension("txt.gz");
+        {
+            let mut encoder = GzEncoder::new(
+                File::create(&gz_path).unwrap(),
+                Compression::default(),
+            );
+            encoder.write_all(&fs::read(&path).unwrap()).unwrap();
+            encoder.finish().unwrap();
+        }
+
+        let plain = DualLog::load(&path).unwrap();
+        let mut gz = SimpleFileLog::new(&gz_path);
+        let all = gz.get_all().unwrap();
+        assert_eq!(all.len(), 5);
+        assert_eq!(
+            all.iter().map(|t| t.to_string()).collect::<Vec<_>>(),
+            plain
+                .get_all()
+                .unwrap()
+                .iter()
+                .map(|t| t.to_string())
+                .collect::<Vec<_>>()
+        );
+        assert_eq!(gz.last().unwrap().unwrap().id().inner(), 5);
+        assert_eq!(gz.get_by_id(3).unwrap().unwrap().id().inner(), 3);
+        assert!(
+            gz.create(TransactionData::new(0, 0, "nope").unwrap(), None)
+                .is_err()
+        );
+        let loaded = DualLog::load(&gz_path).unwrap();
+        assert_eq!(loaded.next_id().unwrap().inner(), 6);
+
+        fs::remove_file(&path).unwrap();
+        fs::remove_file(&gz_path).unwrap();
+    }
+
+    #[test]
+    fn dual_log_publishes_created() {
+        let path = temp_log("broadcast");
+        let broadcast = Arc::new(Broadcast::new());
+        let mut rx = broadcast.subscribe();
+        let mut log =
+            DualLog::load(&path).unwrap().with_broadcast(broadcast.clone());
+        fill(&mut log, 2);
+        assert_eq!(rx.try_recv().unwrap().id().inner(), 1);
+        assert_eq!(rx.try_recv().unwrap().id().inner(), 2);
+        fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn consistency_check_catches_desync() {
+        let path = temp_log("consistency");
+        let mut log = DualLog::load(&path).unwrap();
+        fill(&mut log, 4);
+        assert_eq!(log.check_consistency(), Ok(()));
+
+        let bounded = DualLog::load(&path).unwrap().with_max_len(2);
+        assert_eq!(bounded.check_consistency(), Ok(()));
+
+        let changed = Transaction::new(
+            TransactionId::new(2).unwrap(),
+            "041017-10:00:00".parse().unwrap(),
+            TransactionData::new(1, 2, "changed").unwrap(),
+            None,
+        );
+        let extra = Transaction::new(
+            TransactionId::new(9).unwrap(),
+            "041017-10:00:00".parse().unwrap(),
+            TransactionData::new(1, 2, "extra").unwrap(),
+            None,
+        );
+        log.full_log.log.remove(&1);
+        log.full_log.log.insert(2, changed);
+        log.full_log.log.insert(9, extra);
+        let report = log.check_consistency().unwrap_err();
+        assert_eq!(report.missing, vec![1]);
+        assert_eq!(report.mismatched, vec![2]);
+        assert_eq!(report.extra, vec![9]);
+        assert_eq!(report.unreadable, None);
+        fs::remove_file(&path).unwrap();
+    }
+
+    fn assert_batch_matches_sequential<L: TransactionLog + GetAll>(
+        mut log: L,
+    ) {
+        let item = |i: usize| {
+            (
+                TransactionData::new(1, 2, format!("Transaction Nr. {}", i))
+                    .unwrap(),
+                Some("041017-10:00:00".parse().unwrap()),
+            )
+        };
+        let mut sequential = FullTransactionLog::new();
+        fill(&mut sequential, 5);
+        log.create_batch((0..2).map(&item).collect()).unwrap();
+        let batch = log.create_batch((2..5).map(&item).collect()).unwrap();
+        assert_eq!(batch.len(), 3);
+        assert!(log.create_batch(Vec::new()).unwrap().is_empty());
+
+        let strings = |txs: Vec<Transaction>| {
+            txs.iter().map(|t| t.to_string()).collect::<Vec<_>>()
+        };
+        assert_eq!(
+            strings(log.get_all().unwrap()),
+            strings(sequential.get_all().unwrap())
+        );
+        assert_eq!(
+            log.last().unwrap().map(|t| t.to_string()),
+            batch.last().map(|t| t.to_string())
+        );
+    }
+
+    #[test]
+    fn create_batch_matches_sequential() {
+        assert_batch_matches_sequential(FullTransactionLog::new());
+
+        let path = temp_log("batch_simple");
+        assert_batch_matches_sequential(SimpleFileLog::new(&path));
+        fs::remove_file(&path).unwrap();
+
+        let path = temp_log("batch_dual");
+        assert_batch_matches_sequential(DualLog::load(&path).unwrap());
+        let reloaded = DualLog::load(&path).unwrap();
+        assert_eq!(reloaded.get_all().unwrap().len(), 5);
+        fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn read_only_log() {
+        let path = temp_log("read_only");
+        fill(&mut SimpleFileLog::new(&path), 3);
+        let size = SimpleFileLog::new(&path).disk_bytes().unwrap();
+
+        let mut log = SimpleFileLog::read_only(&path);
+        assert_eq!(log.get_all().unwrap().len(), 3);
+        assert_eq!(log.last().unwrap().unwrap().id().inner(), 3);
+        assert!(log.get_by_id(2).unwrap().is_some());
+
+        let data = TransactionData::new(1, 2, "rejected").unwrap();
+        match log.create(data.clone(), None) {
+            Err(FileError::Other(msg)) => assert_eq!(msg, "read-only log"),
+            other => panic!("unexpected {:?}", other),
+        }
+        assert!(log.create_batch(vec![(data, None)]).is_err());
+        assert_eq!(log.disk_bytes().unwrap(), size);
+        fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn last_with_long_lines() {
+        let path = temp_log("long_lines");
+        let mut log = SimpleFileLog::new(&path).with_chunk_size(16);
+        fill(&mut log, 500);
+        let long = "x".repeat(100 * 1024);
+        log.create(TransactionData::new(1, 2, long.as_str()).unwrap(), None)
+            .unwrap();
+        let last = log.last().unwrap().unwrap();
+        assert_eq!(last.id().inner(), 501);
+        assert_eq!(last.data().text(), long);
+
+        let bounded = SimpleFileLog::new(&path)
+            .with_chunk_size(16)
+            .with_max_tail(64 * 1024);
+        assert!(bounded.last().is_err());
+        fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn last_without_newlines() {
+        let path = temp_log("garbage");
+        File::create(&path)
+            .unwrap()
+            .write_all(&vec![b'x'; 64 * 1024])
+            .unwrap();
+        let log = SimpleFileLog::new(&path).with_max_tail(16 * 1024);
+        match log.last() {
+            Err(FileError::Other(msg)) => assert!(msg.contains("corrupt")),
+            other => panic!("unexpected {:?}", other),
+        }
+        fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn strided_ids() {
+        let path = temp_log("strided");
+        let strided = || {
+            SimpleFileLog::new(&path)
+                .with_allocator(Box::new(StridedAllocator::new(2, 3).unwrap()))
+        };
+        let mut log = DualLog::load_from(strided()).unwrap();
+        fill(&mut log, 2);
+        let batch = log.create_batch(vec![
+            (TransactionData::new(1, 2, "batched").unwrap(), None),
+        ]).unwrap();
+        assert_eq!(batch[0].id().inner(), 8);
+
+        let ids = |txs: Vec<Transaction>| {
+            txs.iter().map(|t| t.id().inner()).collect::<Vec<_>>()
+        };
+        assert_eq!(ids(log.get_all().unwrap()), vec![2, 5, 8]);
+        assert_eq!(ids(strided().get_all().unwrap()), vec![2, 5, 8]);
+        assert_eq!(strided().last().unwrap().unwrap().id().inner(), 8);
+        assert_eq!(log.next_id().unwrap().inner(), 11);
+
+        // consecutive ids are expected without the allocator
+        match SimpleFileLog::new(&path).get_all() {
+            Err(FileError::Verify(e)) => {
+                assert_eq!(e, VerifyError::NonConsecutiveID(2, 5))
+            }
+            other => panic!("unexpected {:?}", other),
+        }
+        fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn recover_corrupt_line() {
+        let path = temp_log("recover");
+        fill(&mut SimpleFileLog::new(&path), 5);
+        let mut backup = path.clone().into_os_string();
+        backup.push(".corrupt");
+        let _ = fs::remove_file(&backup);
+        let mut contents = String::new();
+        File::open(&path)
+            .unwrap()
+            .read_to_string(&mut contents)
+            .unwrap();
+        let corrupt = contents.replace("Nr. 2", "Nr. X");
+        File::create(&path)
+            .unwrap()
+            .write_all(corrupt.as_bytes())
+            .unwrap();
+
+        assert!(DualLog::load(&path).is_err());
+        let (mut log, dropped) = DualLog::load_recovering(&path).unwrap();
+        // the lines after the corrupt one chain onto it and are dropped too
+        assert_eq!(dropped, 3);
+        assert_eq!(log.get_all().unwrap().len(), 2);
+        fill(&mut log, 1);
+        assert_eq!(DualLog::load(&path).unwrap().get_all().unwrap().len(), 3);
+
+        let mut saved = String::new();
+        File::open(&backup)
+            .unwrap()
+            .read_to_string(&mut saved)
+            .unwrap();
+        assert_eq!(saved, corrupt);
+        fs::remove_file(&backup).unwrap();
+        fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn append_detects_external_write() {
+        let path = temp_log("external");
+        let mut log = SimpleFileLog::new(&path);
+        fill(&mut log, 2);
+        let tx = Transaction::new(
+            log.next_id().unwrap(),
+            "041017-10:00:00".parse().unwrap(),
+            TransactionData::new(1, 2, "mine").unwrap(),
+            log.last().unwrap().as_ref(),
+        );
+
+        // another process appends between reading `last()` and writing
+        fill(&mut SimpleFileLog::new(&path), 1);
+        match log.append(&[tx.clone()]) {
+            Err(FileError::Verify(VerifyError::NonConsecutiveID(3, 3))) => {}
+            other => panic!("unexpected {:?}", other),
+        }
+        assert_eq!(log.get_all().unwrap().len(), 3);
+
+        // a transaction built on the new last line is accepted
+        let tx = Transaction::new(
+            log.next_id().unwrap(),
+            "041017-10:00:00".parse().unwrap(),
+            TransactionData::new(1, 2, "mine").unwrap(),
+            log.last().unwrap().as_ref(),
+        );
+        log.append(&[tx]).unwrap();
+        assert_eq!(log.get_all().unwrap().len(), 4);
+        fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn writer_log_chains() {
+        let mut log = WriterLog::new(Vec::new());
+        fill(&mut log, 3);
+        let batch = log.create_batch(vec![
+            (TransactionData::new(3, 4, "batch 1").unwrap(), None),
+            (TransactionData::new(3, 4, "batch 2").unwrap(), None),
+        ]).unwrap();
+        assert_eq!(batch[1].id().inner(), 5);
+        assert_eq!(log.next_id().unwrap().inner(), 6);
+        let last_hash = log.last_hash().unwrap().unwrap();
+        assert_eq!(last_hash.to_string(), batch[1].hash().to_string());
+        assert!(log.last().is_err());
+        assert!(log.get_all().is_err());
+
+        // a transaction not chained onto the last one is refused
+        let mut other = FullTransactionLog::new();
+        fill(&mut other, 1);
+        let unchained = other.last().unwrap().unwrap();
+        assert!(log.append(&[unchained]).is_err());
+
+        let written = String::from_utf8(log.into_inner()).unwrap();
+        let txs: Vec<Transaction> =
+            written.lines().map(|l| l.parse().unwrap()).collect();
+        assert_eq!(txs.len(), 5);
+        let mut prev: Option<&Transaction> = None;
+        for tx in &txs {
+            assert_eq!(verify_transaction(tx, prev), Ok(()));
+            prev = Some(tx);
+        }
+
+        // continuing a chain
+        let mut tee = WriterLog::after(Vec::new(), txs.last().cloned());
+        let tx = tee.create(TransactionData::new(1, 2, "teed").unwrap(), None)
+            .unwrap();
+        assert_eq!(verify_transaction(&tx, txs.last()), Ok(()));
+    }
+
+    #[test]
+    fn rebuild_tampered_log() {
+        let path = temp_log("rebuild");
+        let output = temp_log("rebuilt");
+        fill(&mut SimpleFileLog::new(&path), 5);
+        let mut contents = String::new();
+        File::open(&path)
+            .unwrap()
+            .read_to_string(&mut contents)
+            .unwrap();
+        File::create(&path)
+            .unwrap()
+            .write_all(contents.replace("Nr. 2", "Nr. X").as_bytes())
+            .unwrap();
+        assert!(SimpleFileLog::new(&path).get_all().is_err());
+
+        // the edited transaction and all following it get new hashes
+        assert_eq!(SimpleFileLog::new(&path).rebuild(&output).unwrap(), 3);
+        let all = SimpleFileLog::new(&output).get_all().unwrap();
+        assert_eq!(all.len(), 5);
+        assert_eq!(all[2].data().text(), "Transaction Nr. X");
+        let mut prev: Option<&Transaction> = None;
+        for tx in &all {
+            assert_eq!(verify_transaction(tx, prev), Ok(()));
+            prev = Some(tx);
+        }
+
+        // rebuilding a valid log in place changes nothing
+        assert_eq!(SimpleFileLog::new(&output).rebuild(&output).unwrap(), 0);
+        assert_eq!(SimpleFileLog::new(&output).get_all().unwrap().len(), 5);
+        fs::remove_file(&path).unwrap();
+        fs::remove_file(&output).unwrap();
+    }
+
+    #[test]
+    fn recover_skips_garbage_line() {
+        let path = temp_log("recover_garbage");
+        fill(&mut SimpleFileLog::new(&path), 3);
+        let mut contents = String::new();
+        File::open(&path)
+            .unwrap()
+            .read_to_string(&mut contents)
+            .unwrap();
+        let mut lines: Vec<&str> = contents.lines().collect();
+        lines.insert(2, "garbage");
+        File::create(&path)
+            .unwrap()
+            .write_all(format!("{}\n", lines.join("\n")).as_bytes())
+            .unwrap();
+
+        let (log, dropped) = DualLog::load_recovering(&path).unwrap();
+        assert_eq!(dropped, 1);
+        assert_eq!(log.get_all().unwrap().len(), 3);
+        assert_eq!(DualLog::load_recovering(&path).unwrap().1, 0);
+
+        let mut backup = path.clone().into_os_string();
+        backup.push(".corrupt");
+        fs::remove_file(&backup).unwrap();
+        fs::remove_file(&path).unwrap();
+    }
+
+    /// Adds a garbage line to the end of the file at `path` and recovers
+    /// it, returning the header lines before and after.
+    fn recover_garbage_end(path: &PathBuf) -> (Vec<String>, Vec<String>) {
+        let headers = || {
+            let mut contents = String::new();
+            File::open(path)
+                .unwrap()
+                .read_to_string(&mut contents)
+                .unwrap();
+            contents
+                .lines()
+                .filter(|l| l.starts_with(HEADER_PREFIX))
+                .map(|l| l.to_owned())
+                .collect::<Vec<_>>()
+        };
+        let before = headers();
+        OpenOptions::new()
+            .append(true)
+            .open(path)
+            .unwrap()
+            .write_all(b"garbage\n")
+            .unwrap();
+        assert_eq!(SimpleFileLog::new(path).recover().unwrap(), 1);
+        let mut backup = path.clone().into_os_string();
+        backup.push(".corrupt");
+        fs::remove_file(&backup).unwrap();
+        (before, headers())
+    }
+
+    #[test]
+    fn recover_keeps_headers() {
+        let path = temp_log("recover_format");
+        let iso = LogFormat {
+            time: TimeFormat::Iso,
+            ..LogFormat::default()
+        };
+        fill(&mut SimpleFileLog::new(&path).with_format(iso), 3);
+        let (before, after) = recover_garbage_end(&path);
+        assert_eq!(before, vec!["# format time=iso policy=strict"]);
+        assert_eq!(after, before);
+        assert_eq!(SimpleFileLog::new(&path).format().unwrap(), iso);
+        assert_eq!(SimpleFileLog::new(&path).get_all().unwrap().len(), 3);
+        fs::remove_file(&path).unwrap();
+
+        let path = temp_log("recover_pruned");
+        let mut log = SimpleFileLog::new(&path);
+        for day in &["01", "02", "03"] {
+            let data = TransactionData::new(1, 2, "pruned").unwrap();
+            let ts = format!("{}1017-10:00:00", day).parse().unwrap();
+            log.create(data, Some(ts)).unwrap();
+        }
+        let cutoff = "021017-12:00:00".parse().unwrap();
+        assert_eq!(log.prune_older_than(&cutoff).unwrap(), 2);
+        let (before, after) = recover_garbage_end(&path);
+        assert_eq!(before.len(), 2);
+        assert!(before[1].starts_with("# pruned up to 00000002"));
+        assert_eq!(after, before);
+        assert!(log.pruned_genesis().unwrap().is_some());
+        fs::remove_file(log.sibling(".pruned")).unwrap();
+        fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn crlf_line_endings() {
+        let path = temp_log("crlf");
+        fill(&mut SimpleFileLog::new(&path), 3);
+        let mut contents = String::new();
+        File::open(&path)
+            .unwrap()
+            .read_to_string(&mut contents)
+            .unwrap();
+        File::create(&path)
+            .unwrap()
+            .write_all(contents.replace("\n", "\r\n").as_bytes())
+            .unwrap();
+
+        let mut log = DualLog::load(&path).unwrap();
+        assert_eq!(log.get_all().unwrap().len(), 3);
+        assert_eq!(log.check_consistency(), Ok(()));
+        let file_log = SimpleFileLog::new(&path);
+        assert_eq!(file_log.last().unwrap().unwrap().id().inner(), 3);
+        assert_eq!(file_log.tail(2).unwrap().len(), 2);
+        assert!(file_log.contains(2).unwrap());
+        assert_eq!(
+            file_log.last_hash().unwrap().map(|h| h.to_string()),
+            log.last_hash().unwrap().map(|h| h.to_string())
+        );
+
+        fill(&mut log, 1);
+        assert_eq!(DualLog::load(&path).unwrap().get_all().unwrap().len(), 4);
+        fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn log_quota() {
+        let path = temp_log("quota");
+        let full = |r: Result<Transaction, FileError>| match r {
+            Err(FileError::Other(msg)) => msg == LOG_FULL,
+            _ => false,
+        };
+        let data = |text: &str| TransactionData::new(1, 2, text).unwrap();
+        let mut log = DualLog::load(&path).unwrap().with_max_transactions(3);
+        fill(&mut log, 3);
+        assert!(full(log.create(data("fourth"), None)));
+        assert!(log.create_batch(vec![(data("batch"), None)]).is_err());
+        assert_eq!(log.len(), 3);
+
+        let mut reloaded = DualLog::load(&path)
+            .unwrap()
+            .with_max_len(1)
+            .with_max_transactions(4);
+        assert_eq!(reloaded.len(), 3);
+        fill(&mut reloaded, 1);
+        assert!(full(reloaded.create(data("fifth"), None)));
+
+        let size = SimpleFileLog::new(&path).disk_bytes().unwrap();
+        let time = Some("041017-10:00:00".parse().unwrap());
+        let next = Transaction::new(
+            TransactionId::new(5).unwrap(),
+            "041017-10:00:00".parse().unwrap(),
+            data("sized"),
+            reloaded.last().unwrap().as_ref(),
+        );
+        let limit = size + next.line_len() as u64;
+        let mut sized = DualLog::load(&path).unwrap().with_max_bytes(limit);
+        sized.create(data("sized"), time.clone()).unwrap();
+        assert_eq!(SimpleFileLog::new(&path).disk_bytes().unwrap(), limit);
+        assert!(full(sized.create(data("over"), time)));
+        fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn last_empty() {
+        let path = temp_log("last_empty");
+        let log = SimpleFileLog::new(&path).with_chunk_size(16);
+        assert!(log.last().unwrap().is_none());
+        fs::remove_file(&path).unwrap();
     }
 }